@@ -0,0 +1,205 @@
+//split asm text into lines of tokens, operands use the same notation mkasm prints:
+//  [1] register, @ff address, :name function, (2) stack slot, h1 heap via register,
+//  s1 stack via register, h[1+2*8+10] heap at r1 + r2 * 8 + 0x10 (or -10), j3 jump to byte
+//  index 3 of the program, n5 untyped, 64u5 / 8i7f / 32f3f800000 literals
+//  name: defines a label, &name refers to one
+//  .func name(a, b) declares a function and its arguments, %a refers to an argument in its body
+//operand numbers are hex, anything after a ; is a comment
+use super::AsmError;
+use crate::bytecode::format::pack_name;
+use crate::bytecode::types::Indexed;
+use crate::bytecode::types::Types::{self, *};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    //an op mnemonic such as ADD
+    Word(String),
+    //a directive such as .entry
+    Directive(String),
+    //a bare number, decimal or 0x prefixed hex, only valid as a directive argument
+    Number(u64),
+    Operand(Types, u128),
+    //a label definition, name:
+    Label(String),
+    //a reference to a label, &name
+    LabelRef(String),
+    //the name and argument names after .func
+    Signature(String, Vec<String>),
+    //a reference to an argument of the current function, %name
+    ArgRef(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub number: usize,
+    pub tokens: Vec<Token>,
+}
+
+pub fn tokenize(src: &str) -> Result<Vec<Line>, Vec<AsmError>> {
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    for (i, text) in src.lines().enumerate() {
+        let number = i + 1;
+        let mut text = text.split(';').next().unwrap_or("");
+        let mut tokens = Vec::new();
+        //the signature after .func has its own syntax, so split it off before the words
+        let mut signature = None;
+        if let Some(at) = text.find(".func") {
+            signature = Some(&text[at + ".func".len()..]);
+            text = &text[..at + ".func".len()];
+        }
+        for word in text.split_whitespace() {
+            match token(word) {
+                Ok(token) => tokens.push(token),
+                Err(message) => errors.push(AsmError { line: number, message }),
+            }
+        }
+        if let Some(rest) = signature {
+            match self::signature(rest) {
+                Ok(token) => tokens.push(token),
+                Err(message) => errors.push(AsmError { line: number, message }),
+            }
+        }
+        if !tokens.is_empty() {
+            lines.push(Line { number, tokens });
+        }
+    }
+    if errors.is_empty() {
+        Ok(lines)
+    } else {
+        Err(errors)
+    }
+}
+
+fn token(word: &str) -> Result<Token, String> {
+    let first = word.chars().next().unwrap_or(' ');
+    if first == '.' {
+        return Ok(Token::Directive(word.to_string()));
+    }
+    if let Some(name) = word.strip_suffix(':') {
+        return label(name).map(Token::Label);
+    }
+    if let Some(name) = word.strip_prefix('&') {
+        return label(name).map(Token::LabelRef);
+    }
+    if let Some(name) = word.strip_prefix('%') {
+        return label(name).map(Token::ArgRef);
+    }
+    if first.is_ascii_uppercase() {
+        return Ok(Token::Word(word.to_string()));
+    }
+    let operand = |tp: Types, digits: &str| wide(tp, digits, word).map(|value| Token::Operand(tp, value));
+    if let Some(inner) = word.strip_prefix('[').and_then(|w| w.strip_suffix(']')) {
+        return operand(TypeReg, inner);
+    }
+    if let Some(inner) = word.strip_prefix('(').and_then(|w| w.strip_suffix(')')) {
+        return operand(DerefStack, inner);
+    }
+    if let Some(name) = word.strip_prefix(':') {
+        return match name.strip_prefix('#') {
+            Some(raw) => operand(TypeFunc, raw),
+            None => func(name).map(|value| Token::Operand(TypeFunc, value as u128)),
+        };
+    }
+    if let Some(inner) = word.strip_prefix("h[").and_then(|w| w.strip_suffix(']')) {
+        return indexed(inner, word);
+    }
+    match first {
+        '@' => return operand(TypeAddr, &word[1..]),
+        'h' => return operand(DerefHeapReg, &word[1..]),
+        's' => return operand(DerefStackReg, &word[1..]),
+        'j' => return operand(TypeJmp, &word[1..]),
+        'n' => return operand(NoType, &word[1..]),
+        _ => (),
+    }
+    if let Some(digits) = word.strip_prefix("0x") {
+        return hex(digits, word).map(Token::Number);
+    }
+    if first.is_ascii_digit() {
+        //literals are a width, a kind and hex digits, eg 64u1f
+        let split = word.find(|c: char| !c.is_ascii_digit()).unwrap_or(word.len());
+        let (width, rest) = word.split_at(split);
+        let mut rest = rest.chars();
+        let tp = match (width, rest.next()) {
+            ("8", Some('u')) => TypeU8,
+            ("8", Some('i')) => TypeI8,
+            ("64", Some('u')) => TypeU64,
+            ("64", Some('i')) => TypeI64,
+            ("128", Some('u')) => TypeU128,
+            ("128", Some('i')) => TypeI128,
+            ("32", Some('f')) => TypeF32,
+            ("64", Some('f')) => TypeF64,
+            (_, None) => return word.parse().map(Token::Number).map_err(|_| format!("invalid number {}", word)),
+            _ => return Err(format!("unknown literal type in {}", word)),
+        };
+        return operand(tp, rest.as_str());
+    }
+    Err(format!("unrecognised token {}", word))
+}
+
+fn hex(digits: &str, word: &str) -> Result<u64, String> {
+    u64::from_str_radix(digits, 16).map_err(|_| format!("invalid number in {}", word))
+}
+
+//operand values, only 128 bit literals may be wider than 64 bits
+fn wide(tp: Types, digits: &str, word: &str) -> Result<u128, String> {
+    let value = u128::from_str_radix(digits, 16).map_err(|_| format!("invalid number in {}", word))?;
+    match tp {
+        TypeU128 | TypeI128 => Ok(value),
+        _ if value > u64::MAX as u128 => Err(format!("{} does not fit in 64 bits", word)),
+        _ => Ok(value),
+    }
+}
+
+//base+index*scale followed by an optional +disp or -disp, the inside of h[...]
+fn indexed(inner: &str, word: &str) -> Result<Token, String> {
+    let usage = || format!("indexed operands are h[base+index*scale+disp], found {}", word);
+    let reg = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| format!("invalid number in {}", word));
+    let (base, rest) = inner.split_once('+').ok_or_else(usage)?;
+    let (index, rest) = rest.split_once('*').ok_or_else(usage)?;
+    let (scale, disp) = rest.split_at(rest.find(['+', '-']).unwrap_or(rest.len()));
+    let disp = match disp.split_at(disp.len().min(1)) {
+        ("", _) => 0,
+        ("+", digits) => hex(digits, word)? as i64,
+        (_, digits) => (hex(digits, word)? as i64).wrapping_neg(),
+    };
+    let indexed = Indexed {
+        base: reg(base)?,
+        index: reg(index)?,
+        scale: reg(scale)?,
+        disp,
+    };
+    if !indexed.valid_scale() {
+        return Err(format!("index scale in {} is not 1, 2, 4 or 8", word));
+    }
+    Ok(Token::Operand(DerefIndexed, indexed.pack()))
+}
+
+fn func(name: &str) -> Result<u64, String> {
+    pack_name(name).ok_or(format!("function names must be 1 to 8 ascii characters, found :{}", name))
+}
+
+//name(a, b) after .func
+fn signature(text: &str) -> Result<Token, String> {
+    let usage = || "usage: .func name(arg, ...)".to_string();
+    let (name, rest) = text.trim().split_once('(').ok_or_else(usage)?;
+    let params = rest.strip_suffix(')').ok_or_else(usage)?;
+    let name = name.trim();
+    func(name)?;
+    let params = match params.trim() {
+        "" => Vec::new(),
+        params => params.split(',').map(|param| label(param.trim())).collect::<Result<_, _>>()?,
+    };
+    Ok(Token::Signature(name.to_string(), params))
+}
+
+fn label(name: &str) -> Result<String, String> {
+    let mut chars = name.chars();
+    let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(format!("invalid label name {}", name))
+    }
+}
@@ -4,14 +4,39 @@ use crate::builder::bytes::ByteStream;
 use crate::bytecode::ops::Operations::{self, *};
 //import stream macro
 use crate::{stream, byte, typed, Byte, Types};
+use crate::bytecode::format::func_name;
+use crate::bytecode::types::Indexed;
+
+mod lexer;
+mod parser;
+
+//a problem found while assembling, with the 1-based line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
 
 pub fn mkasm(stream: ByteStream) -> String {
-    println!("{:?}", stream.bytes.len());
     let mut asm = String::new();
+    if stream.entry != 0 {
+        asm.push_str(&format!(".entry {}\n", stream.entry));
+    }
     for byte in stream.bytes {
         match byte.tp {
             TypeOp => {
-                asm.push_str(&format!("\n{:?} ", Operations::from(*(byte.data) as u8)));
+                match Operations::try_from(*(byte.data) as u8) {
+                    Ok(op) => asm.push_str(&format!("\n{:?} ", op)),
+                    Err(code) => asm.push_str(&format!("\n?{:x} ", code)),
+                }
             },
             TypeReg => {
                 asm.push_str(&format!("[{:x}] ", *(byte.data)));
@@ -23,7 +48,13 @@ pub fn mkasm(stream: ByteStream) -> String {
                 asm.push_str(&format!("8u{:x} ", *(byte.data)));
             }
             TypeFunc => {
-                asm.push_str(&format!(":{:?} ", byte.data));
+                //print the packed name when there is one, otherwise the raw value
+                let name = func_name(byte.unwrap());
+                if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    asm.push_str(&format!(":{} ", name));
+                } else {
+                    asm.push_str(&format!(":#{:x} ", *(byte.data)));
+                }
             },
             TypeAddr => {
                 asm.push_str(&format!("@{:x} ", *(byte.data)));
@@ -47,16 +78,28 @@ pub fn mkasm(stream: ByteStream) -> String {
                 asm.push_str(&format!("({:x}) ", *(byte.data)));
             },
             DerefHeapReg => {
-                asm.push_str(&format!("h{:x}", *(byte.data)));
+                asm.push_str(&format!("h{:x} ", *(byte.data)));
             },
             DerefStackReg => {
                 asm.push_str(&format!("s{:x} ", *(byte.data)));
             },
+            DerefIndexed => {
+                let indexed = Indexed::unpack(*(byte.data));
+                let sign = if indexed.disp < 0 { '-' } else { '+' };
+                asm.push_str(&format!(
+                    "h[{:x}+{:x}*{:x}{}{:x}] ",
+                    indexed.base,
+                    indexed.index,
+                    indexed.scale,
+                    sign,
+                    indexed.disp.unsigned_abs()
+                ));
+            },
             NoType => {
-                asm.push_str(&format!("{:x} ", *(byte.data)));
+                asm.push_str(&format!("n{:x} ", *(byte.data)));
             }
             TypeI8 => {
-                asm.push_str(&format!("{:x} ", *(byte.data)));
+                asm.push_str(&format!("8i{:x} ", *(byte.data)));
             }
             TypeJmp => {
                 asm.push_str(&format!("j{:x} ", *(byte.data)));
@@ -65,25 +108,164 @@ pub fn mkasm(stream: ByteStream) -> String {
     }
     asm
 }
-//function to reverse mkasm
-pub fn rvasm(asm: String) -> ByteStream {
-    let mut stream = ByteStream::new();
-    let mut data = Vec::new();
-    for c in asm.chars() {
-        data.push(c as u8);
-    }
-    let mut pos = 0;
-    while pos < data.len() {
-        let tp = Types::from(data[pos]);
-        pos += 1;
-        let arg = data[pos] as u64;
-        pos += 1;
-        let byte = Byte{
-            data: Box::new(arg),
-            pos: 0,
-            tp: tp,
-        };
-        stream.emit(byte);
-    }
-    stream
+//function to reverse mkasm, parse asm text back into a bytestream
+//every problem found is reported, not just the first
+pub fn rvasm(asm: &str) -> Result<ByteStream, Vec<AsmError>> {
+    let lines = lexer::tokenize(asm)?;
+    parser::assemble(&lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(asm: &str) -> Vec<(usize, String)> {
+        let errors = rvasm(asm).expect_err("program should not assemble");
+        errors.into_iter().map(|err| (err.line, err.message)).collect()
+    }
+
+    //the only error assembling asm gives
+    fn error(asm: &str) -> String {
+        let mut errors = errors(asm);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        errors.remove(0).1
+    }
+
+    fn parts(stream: &ByteStream) -> (usize, Vec<(Types, u128)>) {
+        (stream.entry, stream.bytes.iter().map(|b| (b.tp, *b.data)).collect())
+    }
+
+    #[test]
+    fn mkasm_output_assembles_back_to_the_same_program() {
+        let asm = "\
+JMP &main
+.func add(a, b)
+    ADD %a %b
+    RET
+FUNC :raw
+    RET
+main:
+    MOV [1] 64u2
+    MOV [2] 64iffffffffffffffff
+    CALL :add
+    CALL :raw
+    ALLOC [3] 64u20
+    MOV [4] h[3+1*8+10]
+    MOV [4] h[3+1*1-8]
+    MOV [4] h3
+    PUSH 8i7f
+    PUSH (1)
+    MOV [5] s5
+    JZ &main
+    INC @ff 32f3fc00000
+    WRACC n5
+    WRACC 128u123456789abcdef0123456789abcdef
+";
+        let first = rvasm(asm).unwrap();
+        let text = mkasm(first.clone());
+        let second = rvasm(&text).unwrap();
+        assert_eq!(parts(&first), parts(&second));
+        assert_eq!(text, mkasm(second));
+    }
+
+    #[test]
+    fn labels_and_entry_resolve_to_byte_indices() {
+        let stream = rvasm("JMP &end\nNOP\nend:\nNOP\n.entry 2\n").unwrap();
+        assert_eq!((stream.bytes[1].tp, *stream.bytes[1].data), (TypeJmp, 3));
+        assert_eq!(stream.entry, 2);
+        assert!(mkasm(stream).starts_with(".entry 2\n"));
+    }
+
+    #[test]
+    fn unknown_tokens_are_errors() {
+        assert_eq!(error("MOV [1] ?x"), "unrecognised token ?x");
+        assert_eq!(error("MOV [1] 16u1"), "unknown literal type in 16u1");
+    }
+
+    #[test]
+    fn bad_numbers_are_errors() {
+        assert_eq!(error("MOV [1] 64uzz"), "invalid number in 64uzz");
+        assert_eq!(error("MOV [zz] 64u1"), "invalid number in [zz]");
+        assert_eq!(error("MOV [1] 0xzz"), "invalid number in 0xzz");
+        let wide = "MOV [1] 64u10000000000000000";
+        assert_eq!(error(wide), "64u10000000000000000 does not fit in 64 bits");
+    }
+
+    #[test]
+    fn bad_indexed_operands_are_errors() {
+        let usage = "indexed operands are h[base+index*scale+disp], found h[1+2]";
+        assert_eq!(error("MOV [1] h[1+2]"), usage);
+        assert_eq!(error("MOV [1] h[1+2*3]"), "index scale in h[1+2*3] is not 1, 2, 4 or 8");
+        assert_eq!(error("MOV [1] h[1+2*8+zz]"), "invalid number in h[1+2*8+zz]");
+    }
+
+    #[test]
+    fn bad_names_are_errors() {
+        let long = "function names must be 1 to 8 ascii characters, found :waytoolongname";
+        assert_eq!(error("CALL :waytoolongname"), long);
+        assert_eq!(error("bad-label:"), "invalid label name bad-label");
+        assert_eq!(error("JMP &9x"), "invalid label name 9x");
+    }
+
+    #[test]
+    fn bad_func_signatures_are_errors() {
+        assert_eq!(error(".func add"), "usage: .func name(arg, ...)");
+        assert_eq!(error(".func add(a, 1)"), "invalid label name 1");
+        assert_eq!(error(".func add(a, a)\nRET"), "argument a of add is declared twice");
+        let many = "many takes 9 arguments, functions take at most 8";
+        assert_eq!(error(".func many(a, b, c, d, e, f, g, h, i)\nRET"), many);
+    }
+
+    #[test]
+    fn bad_directives_are_errors() {
+        assert_eq!(error(".entry"), "usage: .entry <instruction index>");
+        assert_eq!(error("NOP\n.entry 2"), "entry point 2 is past the end of the program");
+        assert_eq!(error(".bogus"), "unknown directive .bogus");
+    }
+
+    #[test]
+    fn lines_must_start_with_an_instruction() {
+        assert_eq!(error("FOO [1]"), "unknown instruction FOO");
+        assert_eq!(error("[1] MOV"), "expected an instruction, found TypeReg operand");
+    }
+
+    #[test]
+    fn operands_must_match_the_op() {
+        assert_eq!(error("MOV [1]"), "MOV takes 2 operand(s), found 1");
+        assert_eq!(error("MOV 64u1 64u1"), "operand 1 of MOV must be a register, found TypeU64");
+        let store = "STORE takes an address, a length and that many values";
+        assert_eq!(error("STORE [1] 64u2 64u1"), store);
+        assert_eq!(
+            errors("MOV [1] FOO"),
+            [
+                (1, "expected an operand, found FOO".to_string()),
+                (1, "MOV takes 2 operand(s), found 1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn argument_references_must_name_an_argument() {
+        let message = "%x is not an argument of the current function";
+        assert_eq!(error("ADD %x [1]"), message);
+        assert_eq!(error(".func f(a)\nRET\nFUNC :g\nADD %a [1]"), message.replace('x', "a"));
+    }
+
+    #[test]
+    fn labels_must_be_defined_exactly_once() {
+        assert_eq!(error("JMP &nowhere"), "undefined label nowhere");
+        assert_eq!(errors("a:\na:\nNOP"), [(2, "label a is defined more than once".to_string())]);
+    }
+
+    #[test]
+    fn every_error_is_reported_with_its_line() {
+        assert_eq!(
+            errors("NOP\nMOV [1]\nFOO\nJMP &x"),
+            [
+                (2, "MOV takes 2 operand(s), found 1".to_string()),
+                (3, "unknown instruction FOO".to_string()),
+                (4, "undefined label x".to_string()),
+            ]
+        );
+    }
 }
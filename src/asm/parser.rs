@@ -0,0 +1,189 @@
+//turn tokenized lines into a bytestream, checking every op gets the operands its *_ARGS table asks for
+use super::lexer::{Line, Token};
+use super::AsmError;
+use crate::builder::bytes::{Byte, ByteStream};
+use crate::builder::labels::LabelError;
+use crate::bytecode::ops::{ArgType, Operations};
+use crate::bytecode::format::pack_name;
+use crate::bytecode::types::Types::{self, *};
+use crate::engine::ARG_REGS;
+
+pub fn assemble(lines: &[Line]) -> Result<ByteStream, Vec<AsmError>> {
+    let mut stream = ByteStream::new();
+    let mut errors = Vec::new();
+    let mut entry = None;
+    //the line every byte and label came from, for reporting label errors
+    let mut byte_lines = Vec::new();
+    let mut label_lines = Vec::new();
+    //argument names of the function declared by the last .func
+    let mut args: Vec<String> = Vec::new();
+    for line in lines {
+        let mut error = |message: String| errors.push(AsmError { line: line.number, message });
+        let mut tokens = &line.tokens[..];
+        while let Some(Token::Label(name)) = tokens.first() {
+            stream.label(name);
+            label_lines.push(line.number);
+            tokens = &tokens[1..];
+        }
+        let first = match tokens.first() {
+            Some(token) => token,
+            None => continue,
+        };
+        match first {
+            Token::Directive(name) if name == ".entry" => match tokens[1..] {
+                [Token::Number(ip)] => entry = Some((line.number, ip as usize)),
+                _ => error("usage: .entry <instruction index>".to_string()),
+            },
+            Token::Directive(name) if name == ".func" => match &tokens[1..] {
+                [Token::Signature(name, params)] => {
+                    if params.len() > ARG_REGS.len() {
+                        error(format!("{} takes {} arguments, functions take at most {}", name, params.len(), ARG_REGS.len()));
+                    }
+                    for (i, param) in params.iter().enumerate() {
+                        if params[..i].contains(param) {
+                            error(format!("argument {} of {} is declared twice", param, name));
+                        }
+                    }
+                    let packed = pack_name(name).unwrap_or(0);
+                    stream.bytes.push(byte(TypeOp, Operations::FUNC as u128));
+                    stream.bytes.push(byte(TypeFunc, packed as u128));
+                    stream.bytes.push(byte(TypeU8, params.len() as u128));
+                    byte_lines.resize(stream.bytes.len(), line.number);
+                    args = params.clone();
+                }
+                _ => error("usage: .func name(arg, ...)".to_string()),
+            },
+            Token::Directive(name) => error(format!("unknown directive {}", name)),
+            Token::Word(name) => match Operations::from_name(name) {
+                Some(op) => {
+                    //a plain FUNC starts a function without named arguments
+                    if op == Operations::FUNC {
+                        args.clear();
+                    }
+                    let mut operands = Vec::new();
+                    for token in &tokens[1..] {
+                        match token {
+                            Token::Operand(tp, value) => operands.push((*tp, *value, None)),
+                            Token::LabelRef(name) => operands.push((TypeJmp, 0, Some(name))),
+                            Token::ArgRef(name) => match args.iter().position(|arg| arg == name) {
+                                Some(i) => operands.push((TypeReg, (ARG_REGS.start + i) as u128, None)),
+                                None => {
+                                    error(format!("%{} is not an argument of the current function", name));
+                                    //keep the operand count right so only this error is reported
+                                    operands.push((TypeReg, 0, None));
+                                }
+                            },
+                            other => error(format!("expected an operand, found {}", describe(other))),
+                        }
+                    }
+                    let types: Vec<(Types, u128)> = operands.iter().map(|&(tp, value, _)| (tp, value)).collect();
+                    if let Err(message) = check(op, &types) {
+                        error(message);
+                    }
+                    stream.bytes.push(byte(TypeOp, op as u128));
+                    for (tp, value, label) in operands {
+                        match label {
+                            Some(name) => stream.emit_jump(name),
+                            None => stream.emit(byte(tp, value)),
+                        };
+                    }
+                    byte_lines.resize(stream.bytes.len(), line.number);
+                }
+                None => error(format!("unknown instruction {}", name)),
+            },
+            other => error(format!("expected an instruction, found {}", describe(other))),
+        }
+    }
+    if let Err(label_errors) = stream.resolve() {
+        for err in label_errors {
+            let (line, message) = match &err {
+                LabelError::Undefined { name, at } => (byte_lines[*at], format!("undefined label {}", name)),
+                LabelError::Duplicate { name, at } => {
+                    let line = stream
+                        .labels
+                        .iter()
+                        .zip(&label_lines)
+                        .rev()
+                        .find(|((label, pos), _)| label == name && pos == at)
+                        .map_or(byte_lines.get(*at).copied().unwrap_or(0), |(_, &line)| line);
+                    (line, format!("label {} is defined more than once", name))
+                }
+            };
+            errors.push(AsmError { line, message });
+        }
+    }
+    if let Some((line, ip)) = entry {
+        if ip > stream.bytes.len() {
+            errors.push(AsmError {
+                line,
+                message: format!("entry point {} is past the end of the program", ip),
+            });
+        }
+        stream.entry = ip;
+    }
+    errors.sort_by_key(|err| err.line);
+    if errors.is_empty() {
+        Ok(stream)
+    } else {
+        Err(errors)
+    }
+}
+
+//validate operand count and kinds against the op's argument table
+fn check(op: Operations, operands: &[(Types, u128)]) -> Result<(), String> {
+    let args = op.args();
+    let count_ok = match op {
+        //STORE addr len is followed by len values, when len is only known at runtime any count goes
+        Operations::STORE => match operands.get(1) {
+            Some(&(tp, len)) if tp.is_literal() => operands.len() == args.len() + len as usize,
+            Some(_) => true,
+            None => false,
+        },
+        //FUNC name, optionally followed by its argument count
+        Operations::FUNC => matches!(operands.len(), 1 | 2),
+        _ => operands.len() == args.len(),
+    };
+    if !count_ok {
+        return Err(match op {
+            Operations::STORE => "STORE takes an address, a length and that many values".to_string(),
+            Operations::FUNC => "FUNC takes a name and an optional argument count".to_string(),
+            _ => format!("{:?} takes {} operand(s), found {}", op, args.len(), operands.len()),
+        });
+    }
+    for (i, &(tp, _)) in operands.iter().enumerate() {
+        if !op.accepts(i, tp) {
+            let wanted = match op {
+                Operations::INC if i == 0 => "a register or address",
+                Operations::FUNC if i == 0 => "a function name",
+                Operations::FUNC => "an argument count",
+                _ => match args.get(i).unwrap_or(&ArgType::Typed) {
+                    ArgType::Dest => "a register",
+                    ArgType::Func => "a function, jump or address",
+                    ArgType::Typed | ArgType::Untyped => "a value",
+                },
+            };
+            return Err(format!("operand {} of {:?} must be {}, found {:?}", i + 1, op, wanted, tp));
+        }
+    }
+    Ok(())
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) | Token::Directive(word) => word.clone(),
+        Token::Number(n) => n.to_string(),
+        Token::Operand(tp, _) => format!("{:?} operand", tp),
+        Token::Label(name) => format!("label {}:", name),
+        Token::LabelRef(name) => format!("&{}", name),
+        Token::Signature(name, _) => format!("function signature {}", name),
+        Token::ArgRef(name) => format!("%{}", name),
+    }
+}
+
+fn byte(tp: Types, value: u128) -> Byte {
+    Byte {
+        data: Box::new(value),
+        pos: 0,
+        tp,
+    }
+}
@@ -3,17 +3,24 @@ extern crate alloc;
 
 use crate::bytecode::{
     data::ByteData,
+    format,
     ops::ArgType::*,
     ops::Operations::*,
     types::Types::{self, *},
 };
-use crate::reader::Reader;
+use crate::builder::labels::{self, LabelError};
 use alloc::vec::Vec;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ByteStream {
     pos: usize,
     pub bytes: Vec<Byte>,
+    //index of the byte execution starts at
+    pub entry: usize,
+    //labels defined so far and the index they point to
+    pub labels: Vec<(String, usize)>,
+    //placeholder bytes waiting for a label to be resolved
+    pub fixups: Vec<(usize, String)>,
 }
 
 impl From<Vec<ByteStream>> for ByteStream {
@@ -31,25 +38,28 @@ impl From<ByteStream> for Vec<Byte> {
     }
 }
 
-impl From<Vec<u8>> for ByteStream {
-    fn from(bytes: Vec<u8>) -> Self {
-        let mut reader = Reader::new_read(&bytes);
-        reader.group()
+//decode a .cbvm image, as written by the From<ByteStream> for Vec<u8> below
+impl TryFrom<Vec<u8>> for ByteStream {
+    type Error = format::FormatError;
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        format::read_image(&bytes)
     }
 }
 
 impl From<ByteStream> for Vec<u8> {
     fn from(stream: ByteStream) -> Self {
-        let mut bytes = Vec::new();
-        for byte in stream.bytes {
-            bytes.push(*byte.data as u8);
-        }
-        bytes
+        format::write_image(&stream, &[], &[])
     }
 }
 impl From<Vec<Byte>> for ByteStream {
     fn from(bytes: Vec<Byte>) -> Self {
-        ByteStream { pos: 0, bytes }
+        ByteStream {
+            pos: 0,
+            bytes,
+            entry: 0,
+            labels: Vec::new(),
+            fixups: Vec::new(),
+        }
     }
 }
 impl From<&[Byte]> for ByteStream {
@@ -57,6 +67,9 @@ impl From<&[Byte]> for ByteStream {
         ByteStream {
             pos: 0,
             bytes: bytes.to_vec(),
+            entry: 0,
+            labels: Vec::new(),
+            fixups: Vec::new(),
         }
     }
 }
@@ -70,21 +83,15 @@ impl From<&[ByteStream]> for ByteStream {
     }
 }
 
-impl Default for ByteStream {
-    fn default() -> ByteStream {
-        ByteStream {
-            pos: usize::default(),
-            bytes: Vec::default(),
-        }
-    }
-}
-
 impl ByteStream {
     #[allow(dead_code)]
     pub fn new() -> ByteStream {
         ByteStream {
             pos: 0,
             bytes: Vec::new(),
+            entry: 0,
+            labels: Vec::new(),
+            fixups: Vec::new(),
         }
     }
     pub fn emit(&mut self, byte: Byte) -> Self {
@@ -92,11 +99,41 @@ impl ByteStream {
         self.clone()
     }
     pub fn emitstream(&mut self, stream: ByteStream) -> Self {
+        let offset = self.bytes.len();
+        for (name, at) in stream.labels {
+            self.labels.push((name, at + offset));
+        }
+        for (at, name) in stream.fixups {
+            self.fixups.push((at + offset, name));
+        }
         for byte in stream.bytes {
             self.bytes.push(byte);
         }
         self.clone()
     }
+    //define a label pointing at the next byte emitted
+    pub fn label(&mut self, name: &str) -> Self {
+        self.labels.push((name.to_string(), self.bytes.len()));
+        self.clone()
+    }
+    //emit a jump target for a label that may not be defined yet, patched by resolve
+    pub fn emit_jump(&mut self, name: &str) -> Self {
+        self.fixups.push((self.bytes.len(), name.to_string()));
+        self.emit(Byte {
+            data: Box::new(0),
+            pos: 0,
+            tp: Types::TypeJmp,
+        })
+    }
+    //patch every label reference, reporting undefined and duplicate labels
+    pub fn resolve(&mut self) -> Result<(), Vec<LabelError>> {
+        labels::resolve(self)
+    }
+    //write the encoded stream to a .cbvm file
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        let bytes: Vec<u8> = self.clone().into();
+        std::fs::write(path, bytes)
+    }
 
     pub fn stringify(&self) -> String {
         let mut string = String::new();
@@ -111,13 +148,18 @@ impl ByteStream {
 
 #[derive(Debug, Clone)]
 pub struct Byte {
-    pub data: Box<u64>,
+    //wide enough for 128 bit operands, everything else only uses the low 64 bits
+    pub data: Box<u128>,
     pub pos: usize,
     pub tp: Types,
 }
 impl Byte {
+    //the low 64 bits, which is all of the value for anything but 128 bit operands
     pub fn unwrap(&self) -> u64 {
-        *self.data.clone()
+        *self.data as u64
+    }
+    pub fn wide(&self) -> u128 {
+        *self.data
     }
 }
 
@@ -126,7 +168,7 @@ impl Byte {
 macro_rules! typed {
     ($tp:ident, $val:expr) => {
         Byte {
-            data: Box::new($val),
+            data: Box::new(Types::$tp.normalize(($val) as u128)),
             pos: 0,
             tp: Types::$tp,
         }
@@ -137,7 +179,7 @@ macro_rules! op {
     ($op:ident) => {{
         use $crate::bytecode::ops::Operations::*;
         Byte {
-            data: Box::new($op as u64),
+            data: Box::new($op as u128),
             pos: 0,
             tp: Types::TypeOp,
         }
@@ -148,7 +190,7 @@ macro_rules! op {
 macro_rules! constant {
     ($val:expr) => {
         Byte {
-            data: Box::new($val),
+            data: Box::new(($val) as u64 as u128),
             pos: 0,
             tp: Types::TypeU64,
         }
@@ -207,7 +249,7 @@ macro_rules! stream {
 macro_rules! func {
     ($name:expr) => {
         Byte {
-            data: Box::new(stringtohex($name.to_string())),
+            data: Box::new(stringtohex($name.to_string()) as u128),
             pos: 0,
             tp: Types::TypeFunc,
         }
@@ -0,0 +1,82 @@
+//label resolution for bytestreams, labels name an instruction index and references to them are
+//emitted as TypeJmp placeholders that get patched once every label is known
+use crate::builder::bytes::{Byte, ByteStream};
+use crate::bytecode::format::{func_name, symbols};
+use crate::bytecode::ops::Operations::{self, NATIVE};
+use crate::bytecode::types::Types;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelError {
+    //a reference at index `at` names a label that is never defined
+    Undefined { name: String, at: usize },
+    //a label defined at `at` was already defined earlier
+    Duplicate { name: String, at: usize },
+}
+
+impl fmt::Display for LabelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LabelError::Undefined { name, at } => write!(f, "undefined label {} referenced at {}", name, at),
+            LabelError::Duplicate { name, at } => write!(f, "label {} defined again at {}", name, at),
+        }
+    }
+}
+
+impl std::error::Error for LabelError {}
+
+//patch every label reference in the stream, FUNC names count as labels for the start of their body
+//and every TypeFunc operand must name a FUNC in the stream, apart from NATIVE names which are only
+//known once the engine has them registered
+pub fn resolve(stream: &mut ByteStream) -> Result<(), Vec<LabelError>> {
+    let mut errors = Vec::new();
+    let mut targets: HashMap<String, usize> = HashMap::new();
+    let mut define = |name: &str, at: usize, target: usize, errors: &mut Vec<LabelError>| {
+        if targets.insert(name.to_string(), target).is_some() {
+            errors.push(LabelError::Duplicate {
+                name: name.to_string(),
+                at,
+            });
+        }
+    };
+    let funcs = symbols(stream);
+    for func in &funcs {
+        define(&func.name, func.index, func.body(), &mut errors);
+    }
+    for (name, at) in &stream.labels {
+        define(name, *at, *at, &mut errors);
+    }
+
+    for (at, name) in std::mem::take(&mut stream.fixups) {
+        match targets.get(&name) {
+            Some(&target) => {
+                stream.bytes[at].tp = Types::TypeJmp;
+                *stream.bytes[at].data = target as u128;
+            }
+            None => errors.push(LabelError::Undefined { name, at }),
+        }
+    }
+    for (at, byte) in stream.bytes.iter().enumerate() {
+        let native = at > 0 && is_op(&stream.bytes[at - 1], NATIVE);
+        let declared = native || funcs.iter().any(|f| f.index + 1 == at);
+        if let Types::TypeFunc = byte.tp {
+            let name = func_name(byte.unwrap());
+            if !declared && !funcs.iter().any(|f| f.name == name) {
+                errors.push(LabelError::Undefined { name, at });
+            }
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        errors.sort_by_key(|err| match err {
+            LabelError::Undefined { at, .. } | LabelError::Duplicate { at, .. } => *at,
+        });
+        Err(errors)
+    }
+}
+
+fn is_op(byte: &Byte, op: Operations) -> bool {
+    matches!(byte.tp, Types::TypeOp) && byte.unwrap() == op as u64
+}
@@ -1 +1,3 @@
-pub mod bytes;
\ No newline at end of file
+pub mod bytes;
+pub mod labels;
+pub mod program;
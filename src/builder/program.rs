@@ -0,0 +1,427 @@
+//a checked way to build programs, one method per op, every operand is checked against the
+//argument table of its op as it is added and all problems are reported by build()
+use crate::builder::bytes::{Byte, ByteStream};
+use crate::builder::labels::LabelError;
+use crate::bytecode::format::pack_name;
+use crate::bytecode::ops::Operations::{self, *};
+use crate::bytecode::types::{Indexed, Types};
+use crate::engine::ARG_REGS;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Operand {
+    tp: Types,
+    value: u128,
+    //set for references to labels, patched when the program is built
+    label: Option<String>,
+}
+
+impl Operand {
+    fn new(tp: Types, value: u128) -> Operand {
+        Operand {
+            tp,
+            value,
+            label: None,
+        }
+    }
+    pub fn reg(reg: u64) -> Operand {
+        Operand::new(Types::TypeReg, reg as u128)
+    }
+    pub fn u8(value: u8) -> Operand {
+        Operand::new(Types::TypeU8, value as u128)
+    }
+    pub fn u64(value: u64) -> Operand {
+        Operand::new(Types::TypeU64, value as u128)
+    }
+    pub fn i8(value: i8) -> Operand {
+        Operand::new(Types::TypeI8, value as i64 as u64 as u128)
+    }
+    pub fn i64(value: i64) -> Operand {
+        Operand::new(Types::TypeI64, value as u64 as u128)
+    }
+    pub fn f32(value: f32) -> Operand {
+        Operand::new(Types::TypeF32, value.to_bits() as u128)
+    }
+    pub fn f64(value: f64) -> Operand {
+        Operand::new(Types::TypeF64, value.to_bits() as u128)
+    }
+    pub fn u128(value: u128) -> Operand {
+        Operand::new(Types::TypeU128, value)
+    }
+    pub fn i128(value: i128) -> Operand {
+        Operand::new(Types::TypeI128, value as u128)
+    }
+    pub fn addr(addr: u64) -> Operand {
+        Operand::new(Types::TypeAddr, addr as u128)
+    }
+    //a function declared with ProgramBuilder::func
+    pub fn func(name: &str) -> Operand {
+        match pack_name(name) {
+            Some(packed) => Operand::new(Types::TypeFunc, packed as u128),
+            //an unpackable name keeps its text so build() can report it
+            None => Operand {
+                tp: Types::TypeFunc,
+                value: 0,
+                label: Some(name.to_string()),
+            },
+        }
+    }
+    //a label declared with ProgramBuilder::label, it may be declared after it is used
+    pub fn label(name: &str) -> Operand {
+        Operand {
+            tp: Types::TypeJmp,
+            value: 0,
+            label: Some(name.to_string()),
+        }
+    }
+    //the value offset slots down from the top of the stack, 1 is the top
+    pub fn stack(offset: u64) -> Operand {
+        Operand::new(Types::DerefStack, offset as u128)
+    }
+    //the heap byte at the address held in reg
+    pub fn heap_reg(reg: u64) -> Operand {
+        Operand::new(Types::DerefHeapReg, reg as u128)
+    }
+    //the value as many slots down from the top of the stack as reg holds
+    pub fn stack_reg(reg: u64) -> Operand {
+        Operand::new(Types::DerefStackReg, reg as u128)
+    }
+    //scale bytes on the heap at regs[base] + regs[index] * scale + disp
+    pub fn indexed(base: u8, index: u8, scale: u8, disp: i64) -> Operand {
+        let value = Indexed { base, index, scale, disp }.pack();
+        Operand::new(Types::DerefIndexed, value)
+    }
+    //the register argument `index` of a function is passed in, counting from 0
+    pub fn arg(index: usize) -> Operand {
+        Operand::reg((ARG_REGS.start + index) as u64)
+    }
+    pub fn tp(&self) -> Types {
+        self.tp
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    //operand `index` of the op at instruction `at` has a type the op does not accept
+    BadOperand {
+        at: usize,
+        op: Operations,
+        index: usize,
+        found: Types,
+    },
+    //function names must pack into a u64
+    BadName { at: usize, name: String },
+    //more arguments than there are argument registers
+    TooManyArgs { at: usize, count: usize },
+    //an indexed operand scales by something other than 1, 2, 4 or 8
+    BadScale { at: usize, scale: u8 },
+    Label(LabelError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::BadOperand { at, op, index, found } => write!(
+                f,
+                "operand {} of {:?} at {} cannot be {:?}",
+                index + 1,
+                op,
+                at,
+                found
+            ),
+            BuildError::BadName { at, name } => write!(
+                f,
+                "function name {} at {} is not 1 to 8 ascii characters",
+                name, at
+            ),
+            BuildError::TooManyArgs { at, count } => write!(
+                f,
+                "{} arguments at {}, functions take at most {}",
+                count,
+                at,
+                ARG_REGS.len()
+            ),
+            BuildError::BadScale { at, scale } => write!(
+                f,
+                "index scale {} at {} is not 1, 2, 4 or 8",
+                scale, at
+            ),
+            BuildError::Label(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProgramBuilder {
+    stream: ByteStream,
+    errors: Vec<BuildError>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> ProgramBuilder {
+        ProgramBuilder::default()
+    }
+    //resolve labels and hand back the finished stream, or everything that was wrong with it
+    pub fn build(&self) -> Result<ByteStream, Vec<BuildError>> {
+        let mut stream = self.stream.clone();
+        let mut errors = self.errors.clone();
+        if let Err(label_errors) = stream.resolve() {
+            errors.extend(label_errors.into_iter().map(BuildError::Label));
+        }
+        if errors.is_empty() {
+            Ok(stream)
+        } else {
+            Err(errors)
+        }
+    }
+    //index the next instruction will be emitted at
+    pub fn position(&self) -> usize {
+        self.stream.bytes.len()
+    }
+    //start executing at the next instruction
+    pub fn entry(&mut self) -> &mut Self {
+        self.stream.entry = self.position();
+        self
+    }
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        self.stream.label(name);
+        self
+    }
+    fn instr(&mut self, op: Operations, operands: &[Operand]) -> &mut Self {
+        let at = self.position();
+        self.stream.emit(Byte {
+            data: Box::new(op as u128),
+            pos: 0,
+            tp: Types::TypeOp,
+        });
+        for (index, operand) in operands.iter().enumerate() {
+            if !op.accepts(index, operand.tp) {
+                self.errors.push(BuildError::BadOperand {
+                    at,
+                    op,
+                    index,
+                    found: operand.tp,
+                });
+            }
+            let indexed = Indexed::unpack(operand.value);
+            if operand.tp == Types::DerefIndexed && !indexed.valid_scale() {
+                self.errors.push(BuildError::BadScale {
+                    at,
+                    scale: indexed.scale,
+                });
+            }
+            match (&operand.label, operand.tp) {
+                (Some(name), Types::TypeJmp) => {
+                    self.stream.emit_jump(name);
+                }
+                (Some(name), _) => {
+                    self.errors.push(BuildError::BadName {
+                        at,
+                        name: name.clone(),
+                    });
+                    //untyped so label resolution does not report the name a second time
+                    self.stream.emit(Byte {
+                        data: Box::new(0),
+                        pos: 0,
+                        tp: Types::NoType,
+                    });
+                }
+                (None, tp) => {
+                    self.stream.emit(Byte {
+                        data: Box::new(operand.value),
+                        pos: 0,
+                        tp,
+                    });
+                }
+            }
+        }
+        self
+    }
+
+    pub fn nop(&mut self) -> &mut Self {
+        self.instr(NOP, &[])
+    }
+    //arithmetic, the result goes in the accumulator
+    pub fn add(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(ADD, &[left, right])
+    }
+    pub fn sub(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(SUB, &[left, right])
+    }
+    pub fn mul(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(MUL, &[left, right])
+    }
+    pub fn div(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(DIV, &[left, right])
+    }
+    pub fn modulo(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(MOD, &[left, right])
+    }
+    //bitwise
+    pub fn and(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(AND, &[left, right])
+    }
+    pub fn or(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(OR, &[left, right])
+    }
+    pub fn xor(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(XOR, &[left, right])
+    }
+    pub fn not(&mut self, reg: Operand) -> &mut Self {
+        self.instr(NOT, &[reg])
+    }
+    //comparison, 1 or 0 goes in the accumulator
+    pub fn eq(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(EQ, &[left, right])
+    }
+    pub fn neq(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(NEQ, &[left, right])
+    }
+    pub fn lt(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(LT, &[left, right])
+    }
+    pub fn gt(&mut self, left: Operand, right: Operand) -> &mut Self {
+        self.instr(GT, &[left, right])
+    }
+    //stack
+    pub fn push(&mut self, value: Operand) -> &mut Self {
+        self.instr(PUSH, &[value])
+    }
+    pub fn pop(&mut self, reg: Operand) -> &mut Self {
+        self.instr(POP, &[reg])
+    }
+    pub fn dup(&mut self) -> &mut Self {
+        self.instr(DUP, &[])
+    }
+    pub fn swap(&mut self) -> &mut Self {
+        self.instr(SWAP, &[])
+    }
+    //control flow
+    pub fn jmp(&mut self, target: Operand) -> &mut Self {
+        self.instr(JMP, &[target])
+    }
+    pub fn halt(&mut self, code: Operand) -> &mut Self {
+        self.instr(HALT, &[code])
+    }
+    pub fn jz(&mut self, target: Operand) -> &mut Self {
+        self.instr(JZ, &[target])
+    }
+    pub fn jnz(&mut self, target: Operand) -> &mut Self {
+        self.instr(JNZ, &[target])
+    }
+    //memory
+    pub fn load(&mut self, reg: Operand, addr: Operand) -> &mut Self {
+        self.instr(LOAD, &[reg, addr])
+    }
+    //store every value from addr onwards, the length operand is filled in from values
+    pub fn store(&mut self, addr: Operand, values: &[Operand]) -> &mut Self {
+        let mut operands = vec![addr, Operand::u64(values.len() as u64)];
+        operands.extend_from_slice(values);
+        self.instr(STORE, &operands)
+    }
+    //reg = the little endian value at base+offset
+    pub fn load8(&mut self, reg: Operand, base: Operand, offset: Operand) -> &mut Self {
+        self.instr(LOAD8, &[reg, base, offset])
+    }
+    pub fn load16(&mut self, reg: Operand, base: Operand, offset: Operand) -> &mut Self {
+        self.instr(LOAD16, &[reg, base, offset])
+    }
+    pub fn load32(&mut self, reg: Operand, base: Operand, offset: Operand) -> &mut Self {
+        self.instr(LOAD32, &[reg, base, offset])
+    }
+    pub fn load64(&mut self, reg: Operand, base: Operand, offset: Operand) -> &mut Self {
+        self.instr(LOAD64, &[reg, base, offset])
+    }
+    //store the low bits of value at base+offset, little endian
+    pub fn store8(&mut self, base: Operand, offset: Operand, value: Operand) -> &mut Self {
+        self.instr(STORE8, &[base, offset, value])
+    }
+    pub fn store16(&mut self, base: Operand, offset: Operand, value: Operand) -> &mut Self {
+        self.instr(STORE16, &[base, offset, value])
+    }
+    pub fn store32(&mut self, base: Operand, offset: Operand, value: Operand) -> &mut Self {
+        self.instr(STORE32, &[base, offset, value])
+    }
+    pub fn store64(&mut self, base: Operand, offset: Operand, value: Operand) -> &mut Self {
+        self.instr(STORE64, &[base, offset, value])
+    }
+    pub fn alloc(&mut self, reg: Operand, size: Operand) -> &mut Self {
+        self.instr(ALLOC, &[reg, size])
+    }
+    pub fn free(&mut self, addr: Operand) -> &mut Self {
+        self.instr(FREE, &[addr])
+    }
+    pub fn realloc(&mut self, reg: Operand, size: Operand) -> &mut Self {
+        self.instr(REALLOC, &[reg, size])
+    }
+    //io
+    pub fn write(&mut self, addr: Operand, size: Operand) -> &mut Self {
+        self.instr(WRITE, &[addr, size])
+    }
+    pub fn read(&mut self, addr: Operand, size: Operand) -> &mut Self {
+        self.instr(READ, &[addr, size])
+    }
+    //read up to max bytes, stopping after delim
+    pub fn read_until(&mut self, addr: Operand, max: Operand, delim: Operand) -> &mut Self {
+        self.instr(READUNTIL, &[addr, max, delim])
+    }
+    pub fn read_line(&mut self, addr: Operand, max: Operand) -> &mut Self {
+        self.instr(READLINE, &[addr, max])
+    }
+    pub fn flush(&mut self) -> &mut Self {
+        self.instr(FLUSH, &[])
+    }
+    //registers
+    pub fn mov(&mut self, reg: Operand, value: Operand) -> &mut Self {
+        self.instr(MOV, &[reg, value])
+    }
+    pub fn inc(&mut self, target: Operand, amount: Operand) -> &mut Self {
+        self.instr(INC, &[target, amount])
+    }
+    pub fn dec(&mut self, reg: Operand) -> &mut Self {
+        self.instr(DEC, &[reg])
+    }
+    //functions
+    pub fn func(&mut self, name: &str) -> &mut Self {
+        self.instr(FUNC, &[Operand::func(name)])
+    }
+    //a function taking args arguments, read them in the body with Operand::arg
+    pub fn func_args(&mut self, name: &str, args: usize) -> &mut Self {
+        self.check_args(args);
+        self.instr(FUNC, &[Operand::func(name), Operand::u8(args as u8)])
+    }
+    //return to the caller, the result is whatever is left in the accumulator
+    pub fn ret(&mut self) -> &mut Self {
+        self.instr(RET, &[])
+    }
+    pub fn call(&mut self, target: Operand) -> &mut Self {
+        self.instr(CALL, &[target])
+    }
+    //move args into the argument registers then call target
+    pub fn call_with(&mut self, target: Operand, args: &[Operand]) -> &mut Self {
+        self.check_args(args.len());
+        for (index, arg) in args.iter().take(ARG_REGS.len()).enumerate() {
+            self.mov(Operand::arg(index), arg.clone());
+        }
+        self.call(target)
+    }
+    fn check_args(&mut self, count: usize) {
+        if count > ARG_REGS.len() {
+            let at = self.position();
+            self.errors.push(BuildError::TooManyArgs { at, count });
+        }
+    }
+    //call a function the host registered with Engine::register_native
+    pub fn native(&mut self, name: &str) -> &mut Self {
+        self.instr(NATIVE, &[Operand::func(name)])
+    }
+    //accumulator
+    pub fn wracc(&mut self, value: Operand) -> &mut Self {
+        self.instr(WRACC, &[value])
+    }
+    pub fn reacc(&mut self, reg: Operand) -> &mut Self {
+        self.instr(REACC, &[reg])
+    }
+}
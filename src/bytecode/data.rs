@@ -11,16 +11,15 @@ impl std::fmt::Debug for dyn ByteData {
 impl Clone for Box<dyn ByteData> {
     #[allow(unconditional_recursion)]
     fn clone(&self) -> Self {
-        return Box::new((*self).clone())
+        Box::new((*self).clone())
     }
 }
 
-#[allow(unconditional_recursion)]
 impl ByteData for Box<dyn ByteData> {
     fn get(&self) -> u8 {
-        return (*self).get()
+        (**self).get()
     }
     fn set(&mut self, data: u8) {
-        return (*self).set(data)
+        (**self).set(data)
     }
 }
\ No newline at end of file
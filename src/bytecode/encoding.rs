@@ -0,0 +1,113 @@
+//operand encoding for .cbvm files, every Byte is written as its type tag followed by its data
+//fixed width types are little endian, index-like and narrow types are LEB128 varints
+use crate::builder::bytes::Byte;
+use crate::bytecode::format::FormatError;
+use crate::bytecode::types::Types;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Fixed(usize),
+    Varint,
+    SignedVarint,
+}
+
+//write a single Byte (type tag then data) to the end of out
+pub fn encode(byte: &Byte, out: &mut Vec<u8>) {
+    let data = *byte.data;
+    out.push(byte.tp as u8);
+    match byte.tp.encoding() {
+        Encoding::Fixed(width) => out.extend_from_slice(&data.to_le_bytes()[..width]),
+        //only 128 bit types are wider than 64 bits and they are fixed width
+        Encoding::Varint => write_uleb(data as u64, out),
+        Encoding::SignedVarint => write_sleb(data as i64, out),
+    }
+}
+
+//read a single Byte starting at pos, moving pos past it
+pub fn decode(stream: &[u8], pos: &mut usize) -> Result<Byte, FormatError> {
+    let tag = *stream.get(*pos).ok_or(FormatError::Truncated { offset: *pos })?;
+    *pos += 1;
+    let tp = Types::from(tag);
+    let data = match tp.encoding() {
+        Encoding::Fixed(width) => {
+            let bytes = stream
+                .get(*pos..*pos + width)
+                .ok_or(FormatError::Truncated { offset: *pos })?;
+            let mut buf = [0u8; 16];
+            buf[..width].copy_from_slice(bytes);
+            *pos += width;
+            u128::from_le_bytes(buf)
+        }
+        Encoding::Varint => read_uleb(stream, pos)? as u128,
+        Encoding::SignedVarint => read_sleb(stream, pos)? as u64 as u128,
+    };
+    Ok(Byte {
+        data: Box::new(data),
+        pos: 0,
+        tp,
+    })
+}
+
+pub fn write_uleb(mut value: u64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn write_sleb(mut value: i64, out: &mut Vec<u8>) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+pub fn read_uleb(stream: &[u8], pos: &mut usize) -> Result<u64, FormatError> {
+    let start = *pos;
+    let mut result = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *stream.get(*pos).ok_or(FormatError::Truncated { offset: *pos })?;
+        *pos += 1;
+        if shift >= 64 {
+            return Err(FormatError::VarintOverflow { offset: start });
+        }
+        result |= ((byte & 0x7f) as u64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+}
+
+pub fn read_sleb(stream: &[u8], pos: &mut usize) -> Result<i64, FormatError> {
+    let start = *pos;
+    let mut result = 0i64;
+    let mut shift = 0;
+    loop {
+        let byte = *stream.get(*pos).ok_or(FormatError::Truncated { offset: *pos })?;
+        *pos += 1;
+        if shift >= 64 {
+            return Err(FormatError::VarintOverflow { offset: start });
+        }
+        result |= ((byte & 0x7f) as i64) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if shift < 64 && byte & 0x40 != 0 {
+                result |= -1i64 << shift;
+            }
+            return Ok(result);
+        }
+    }
+}
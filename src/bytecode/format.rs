@@ -0,0 +1,493 @@
+//layout of a .cbvm file
+//
+//  magic "CBVM" | version u16 | flags u16 | entry u64 | section count u16 | section table | sections
+//
+//each entry in the section table is a kind u8, offset u32 and size u32, offsets are from the start
+//of the file and every integer is little endian
+use crate::builder::bytes::{Byte, ByteStream};
+use crate::bytecode::encoding;
+use crate::bytecode::ops::Operations;
+use crate::bytecode::types::Types;
+use std::fmt;
+
+pub const MAGIC: [u8; 4] = *b"CBVM";
+pub const VERSION: u16 = 1;
+//set when the file carries a debug info section
+pub const FLAG_DEBUG: u16 = 0x0001;
+const KNOWN_FLAGS: u16 = FLAG_DEBUG;
+
+const HEADER_SIZE: usize = 18;
+const SECTION_ENTRY_SIZE: usize = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    Truncated { offset: usize },
+    VarintOverflow { offset: usize },
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnknownFlags(u16),
+    UnknownSection(u8),
+    DuplicateSection(SectionKind),
+    SectionOutOfBounds(SectionKind),
+    MissingCode,
+    BadEntry(u64),
+    BadSymbol { offset: usize },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Truncated { offset } => write!(f, "unexpected end of data at offset {}", offset),
+            FormatError::VarintOverflow { offset } => write!(f, "varint too long at offset {}", offset),
+            FormatError::BadMagic => write!(f, "not a cbvm file (bad magic number)"),
+            FormatError::UnsupportedVersion(v) => write!(f, "unsupported format version {} (expected {})", v, VERSION),
+            FormatError::UnknownFlags(flags) => write!(f, "unknown header flags {:#06x}", flags),
+            FormatError::UnknownSection(kind) => write!(f, "unknown section kind {:#04x}", kind),
+            FormatError::DuplicateSection(kind) => write!(f, "duplicate {:?} section", kind),
+            FormatError::SectionOutOfBounds(kind) => write!(f, "{:?} section lies outside the file", kind),
+            FormatError::MissingCode => write!(f, "file has no code section"),
+            FormatError::BadEntry(entry) => write!(f, "entry point {} is outside the code section", entry),
+            FormatError::BadSymbol { offset } => write!(f, "malformed symbol at offset {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    Code = 0x01,
+    Data = 0x02,
+    Symbols = 0x03,
+    Debug = 0x04,
+}
+
+impl TryFrom<u8> for SectionKind {
+    type Error = FormatError;
+    fn try_from(kind: u8) -> Result<Self, Self::Error> {
+        match kind {
+            0x01 => Ok(SectionKind::Code),
+            0x02 => Ok(SectionKind::Data),
+            0x03 => Ok(SectionKind::Symbols),
+            0x04 => Ok(SectionKind::Debug),
+            _ => Err(FormatError::UnknownSection(kind)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl Section {
+    pub fn range(&self) -> std::ops::Range<usize> {
+        self.offset as usize..self.offset as usize + self.size as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub version: u16,
+    pub flags: u16,
+    pub entry: u64,
+    pub sections: Vec<Section>,
+}
+
+impl Header {
+    //parse the header and section table at the start of stream, checking everything that can be
+    //checked without decoding the sections themselves
+    pub fn parse(stream: &[u8]) -> Result<Header, FormatError> {
+        if stream.len() < MAGIC.len() || stream[..MAGIC.len()] != MAGIC {
+            return Err(FormatError::BadMagic);
+        }
+        let fixed = stream
+            .get(..HEADER_SIZE)
+            .ok_or(FormatError::Truncated { offset: stream.len() })?;
+        let version = u16::from_le_bytes([fixed[4], fixed[5]]);
+        if version != VERSION {
+            return Err(FormatError::UnsupportedVersion(version));
+        }
+        let flags = u16::from_le_bytes([fixed[6], fixed[7]]);
+        if flags & !KNOWN_FLAGS != 0 {
+            return Err(FormatError::UnknownFlags(flags & !KNOWN_FLAGS));
+        }
+        let mut entry = [0u8; 8];
+        entry.copy_from_slice(&fixed[8..16]);
+        let entry = u64::from_le_bytes(entry);
+        let count = u16::from_le_bytes([fixed[16], fixed[17]]) as usize;
+
+        let mut sections: Vec<Section> = Vec::new();
+        for i in 0..count {
+            let offset = HEADER_SIZE + i * SECTION_ENTRY_SIZE;
+            let raw = stream
+                .get(offset..offset + SECTION_ENTRY_SIZE)
+                .ok_or(FormatError::Truncated { offset })?;
+            let kind = SectionKind::try_from(raw[0])?;
+            if sections.iter().any(|s| s.kind == kind) {
+                return Err(FormatError::DuplicateSection(kind));
+            }
+            let section = Section {
+                kind,
+                offset: u32::from_le_bytes([raw[1], raw[2], raw[3], raw[4]]),
+                size: u32::from_le_bytes([raw[5], raw[6], raw[7], raw[8]]),
+            };
+            if section.range().end > stream.len() {
+                return Err(FormatError::SectionOutOfBounds(kind));
+            }
+            sections.push(section);
+        }
+        let header = Header {
+            version,
+            flags,
+            entry,
+            sections,
+        };
+        if header.section(SectionKind::Code).is_none() {
+            return Err(FormatError::MissingCode);
+        }
+        Ok(header)
+    }
+    pub fn section(&self, kind: SectionKind) -> Option<&Section> {
+        self.sections.iter().find(|s| s.kind == kind)
+    }
+    //size of the header plus section table, the first section starts here
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + self.sections.len() * SECTION_ENTRY_SIZE
+    }
+    pub fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&self.flags.to_le_bytes());
+        out.extend_from_slice(&self.entry.to_le_bytes());
+        out.extend_from_slice(&(self.sections.len() as u16).to_le_bytes());
+        for section in &self.sections {
+            out.push(section.kind as u8);
+            out.extend_from_slice(&section.offset.to_le_bytes());
+            out.extend_from_slice(&section.size.to_le_bytes());
+        }
+    }
+}
+
+impl fmt::Display for Header {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "CBVM file, format version {}", self.version)?;
+        writeln!(f, "flags:       {:#06x}{}", self.flags, if self.flags & FLAG_DEBUG != 0 { " (debug)" } else { "" })?;
+        writeln!(f, "entry point: {}", self.entry)?;
+        writeln!(f, "sections:")?;
+        for section in &self.sections {
+            writeln!(
+                f,
+                "  {:<8} offset {:#08x}  size {} bytes",
+                format!("{:?}", section.kind),
+                section.offset,
+                section.size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+//a named position in the code section, taken from FUNC labels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub index: usize,
+    //how many argument registers the function takes, when its FUNC says
+    pub args: Option<usize>,
+}
+
+impl Symbol {
+    //index of the first instruction of the function body
+    pub fn body(&self) -> usize {
+        self.index + if self.args.is_some() { 3 } else { 2 }
+    }
+}
+
+//collect a symbol for every FUNC instruction in the stream
+pub fn symbols(stream: &ByteStream) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for (i, pair) in stream.bytes.windows(2).enumerate() {
+        if is_func(&pair[0]) {
+            if let Types::TypeFunc = pair[1].tp {
+                let args = stream
+                    .bytes
+                    .get(i + 2)
+                    .filter(|byte| byte.tp.is_literal())
+                    .map(|byte| byte.unwrap() as usize);
+                symbols.push(Symbol {
+                    name: func_name(pair[1].unwrap()),
+                    index: i,
+                    args,
+                });
+            }
+        }
+    }
+    symbols
+}
+
+fn is_func(byte: &Byte) -> bool {
+    matches!(byte.tp, Types::TypeOp) && byte.unwrap() == Operations::FUNC as u64
+}
+
+//names are packed into a u64 by stringtohex, undo that and drop the leading padding
+pub fn func_name(data: u64) -> String {
+    data.to_be_bytes()
+        .iter()
+        .skip_while(|&&b| b == 0)
+        .map(|&b| b as char)
+        .collect()
+}
+
+//pack a function name into a u64 the same way stringtohex does, names are 1 to 8 ascii characters
+pub fn pack_name(name: &str) -> Option<u64> {
+    if name.is_empty() || name.len() > 8 || !name.is_ascii() {
+        return None;
+    }
+    Some(name.bytes().fold(0u64, |packed, b| (packed << 8) | b as u64))
+}
+
+pub fn encode_symbols(symbols: &[Symbol], out: &mut Vec<u8>) {
+    encoding::write_uleb(symbols.len() as u64, out);
+    for symbol in symbols {
+        encoding::write_uleb(symbol.name.len() as u64, out);
+        out.extend_from_slice(symbol.name.as_bytes());
+        encoding::write_uleb(symbol.index as u64, out);
+        //0 when the argument count is not known, otherwise the count plus one
+        encoding::write_uleb(symbol.args.map_or(0, |args| args as u64 + 1), out);
+    }
+}
+
+pub fn decode_symbols(section: &[u8]) -> Result<Vec<Symbol>, FormatError> {
+    let mut pos = 0;
+    let count = encoding::read_uleb(section, &mut pos)?;
+    let mut symbols = Vec::new();
+    for _ in 0..count {
+        let start = pos;
+        let len = encoding::read_uleb(section, &mut pos)? as usize;
+        let name = pos
+            .checked_add(len)
+            .and_then(|end| section.get(pos..end))
+            .and_then(|name| std::str::from_utf8(name).ok())
+            .ok_or(FormatError::BadSymbol { offset: start })?
+            .to_string();
+        pos += len;
+        let index = encoding::read_uleb(section, &mut pos)? as usize;
+        let args = match encoding::read_uleb(section, &mut pos)? {
+            0 => None,
+            args => Some(args as usize - 1),
+        };
+        symbols.push(Symbol { name, index, args });
+    }
+    Ok(symbols)
+}
+
+//decode a complete file straight from memory, the inverse of write_image
+pub fn read_image(image: &[u8]) -> Result<ByteStream, FormatError> {
+    decode_code(image, &Header::parse(image)?)
+}
+
+//decode every type tag and its data in the code section of an already parsed image
+pub fn decode_code(image: &[u8], header: &Header) -> Result<ByteStream, FormatError> {
+    let code = header
+        .section(SectionKind::Code)
+        .ok_or(FormatError::MissingCode)?
+        .range();
+    let mut stream = ByteStream::new();
+    let mut pos = code.start;
+    while pos < code.end {
+        stream.bytes.push(encoding::decode(&image[..code.end], &mut pos)?);
+    }
+    if header.entry as usize > stream.bytes.len() {
+        return Err(FormatError::BadEntry(header.entry));
+    }
+    stream.entry = header.entry as usize;
+    Ok(stream)
+}
+
+//build a complete file from a code stream plus optional constant data and debug info
+pub fn write_image(code: &ByteStream, data: &[u8], debug: &[u8]) -> Vec<u8> {
+    let mut payloads: Vec<(SectionKind, Vec<u8>)> = Vec::new();
+    let mut encoded = Vec::new();
+    for byte in &code.bytes {
+        encoding::encode(byte, &mut encoded);
+    }
+    payloads.push((SectionKind::Code, encoded));
+    if !data.is_empty() {
+        payloads.push((SectionKind::Data, data.to_vec()));
+    }
+    let symbols = symbols(code);
+    if !symbols.is_empty() {
+        let mut encoded = Vec::new();
+        encode_symbols(&symbols, &mut encoded);
+        payloads.push((SectionKind::Symbols, encoded));
+    }
+    if !debug.is_empty() {
+        payloads.push((SectionKind::Debug, debug.to_vec()));
+    }
+
+    let mut header = Header {
+        version: VERSION,
+        flags: if debug.is_empty() { 0 } else { FLAG_DEBUG },
+        entry: code.entry as u64,
+        sections: Vec::new(),
+    };
+    let mut offset = HEADER_SIZE + payloads.len() * SECTION_ENTRY_SIZE;
+    for (kind, payload) in &payloads {
+        header.sections.push(Section {
+            kind: *kind,
+            offset: offset as u32,
+            size: payload.len() as u32,
+        });
+        offset += payload.len();
+    }
+    let mut out = Vec::with_capacity(offset);
+    header.write(&mut out);
+    for (_, payload) in payloads {
+        out.extend_from_slice(&payload);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::bytes::Byte;
+    use crate::bytecode::types::Indexed;
+    use crate::bytecode::types::Types::{self, *};
+
+    fn byte(tp: Types, data: u128) -> Byte {
+        Byte {
+            data: Box::new(data),
+            pos: 0,
+            tp,
+        }
+    }
+
+    fn round_trip(stream: &ByteStream) -> ByteStream {
+        let image: Vec<u8> = stream.clone().into();
+        ByteStream::try_from(image).expect("image should decode")
+    }
+
+    fn assert_same(left: &ByteStream, right: &ByteStream) {
+        assert_eq!(left.entry, right.entry);
+        let left: Vec<_> = left.bytes.iter().map(|b| (b.tp, *b.data)).collect();
+        let right: Vec<_> = right.bytes.iter().map(|b| (b.tp, *b.data)).collect();
+        assert_eq!(left, right);
+    }
+
+    #[test]
+    fn every_operand_type_round_trips_bit_for_bit() {
+        let indexed = Indexed { base: 1, index: 2, scale: 8, disp: -16 };
+        let operands = [
+            byte(TypeU8, 0xff),
+            byte(TypeU64, u64::MAX as u128),
+            byte(TypeI8, -128i8 as u64 as u128),
+            byte(TypeI64, i64::MIN as u64 as u128),
+            byte(TypeF32, 1.5f32.to_bits() as u128),
+            byte(TypeF64, (-0.25f64).to_bits() as u128),
+            byte(TypeU128, u128::MAX),
+            byte(TypeI128, -2i128 as u128),
+            byte(TypeAddr, 300),
+            byte(TypeReg, 59),
+            byte(TypeFunc, pack_name("main").unwrap() as u128),
+            byte(DerefStack, 3),
+            byte(DerefHeapReg, 2),
+            byte(DerefStackReg, 1),
+            byte(TypeJmp, 0),
+            byte(DerefIndexed, indexed.pack()),
+            byte(NoType, 5),
+        ];
+        let mut stream = ByteStream::new();
+        stream.emit(byte(TypeOp, 0));
+        for operand in operands {
+            stream.emit(operand);
+        }
+        stream.entry = 0;
+        assert_same(&stream, &round_trip(&stream));
+    }
+
+    #[test]
+    fn type_tags_keep_their_values() {
+        //NoType was written as 0x10 before DerefIndexed existed, so that tag still means NoType
+        assert_eq!(NoType as u8, 0x10);
+        assert_eq!(Types::from(0x10), NoType);
+        assert_eq!(Types::from(0x11), DerefIndexed);
+        for tag in 0..=0x11 {
+            assert_eq!(Types::from(tag) as u8, tag);
+        }
+    }
+
+    #[test]
+    fn negative_literals_keep_their_width() {
+        use crate::{constant, op, typed};
+        let literals = [
+            typed!(TypeI64, -1i64),
+            typed!(TypeI8, -2i8),
+            typed!(TypeI128, -1i128),
+            constant!(-1i64),
+        ];
+        let mut stream = ByteStream::new();
+        for (reg, literal) in literals.into_iter().enumerate() {
+            stream.emit(op!(MOV));
+            stream.emit(typed!(TypeReg, reg + 1));
+            stream.emit(literal);
+        }
+        assert_eq!(*stream.bytes[2].data, u64::MAX as u128);
+        assert_eq!(*stream.bytes[5].data, -2i64 as u64 as u128);
+        assert_eq!(*stream.bytes[8].data, u128::MAX);
+        assert_eq!(*stream.bytes[11].data, u64::MAX as u128);
+        assert_same(&stream, &round_trip(&stream));
+        //mkasm prints them in a form rvasm reads back
+        let text = crate::asm::mkasm(stream.clone());
+        assert_same(&stream, &crate::asm::rvasm(&text).expect("mkasm output should assemble"));
+    }
+
+    #[test]
+    fn programs_behave_the_same_after_a_round_trip() {
+        let programs = [
+            "MOV [1] 64iffffffffffffffff\nADD [1] 128u1\n",
+            "MOV [1] 32f3fc00000\nMOV [2] 64f4000000000000000\nMUL [1] [2]\nREACC [3]\n",
+            "MOV [1] 128iffffffffffffffffffffffffffffffff\nPUSH [1]\nLT (1) 8i7f\n",
+        ];
+        for asm in programs {
+            let stream = crate::asm::rvasm(asm).expect("program should assemble");
+            let mut engine = crate::engine::Engine::new();
+            let status = engine.run(stream.clone());
+            let mut loaded = crate::engine::Engine::new();
+            assert_eq!(loaded.run(round_trip(&stream)), status);
+            assert_eq!(engine.accumulator(), loaded.accumulator());
+            assert_eq!(engine.regs.data, loaded.regs.data);
+            assert_eq!(engine.stack(), loaded.stack());
+        }
+    }
+
+    #[test]
+    fn entry_and_symbols_round_trip() {
+        let stream = crate::asm::rvasm(".func add(a, b)\n    ADD %a %b\n    RET\n    NOP\n.entry 7\n")
+            .expect("program should assemble");
+        let loaded = round_trip(&stream);
+        assert_same(&stream, &loaded);
+        assert_eq!(symbols(&stream), symbols(&loaded));
+    }
+
+    #[test]
+    fn symbol_lengths_past_the_section_are_errors() {
+        //one symbol whose name is u64::MAX bytes long
+        let mut section = vec![1];
+        encoding::write_uleb(u64::MAX, &mut section);
+        assert_eq!(decode_symbols(&section), Err(FormatError::BadSymbol { offset: 1 }));
+    }
+
+    #[test]
+    fn malformed_images_are_errors_not_panics() {
+        let image: Vec<u8> = crate::asm::rvasm("    MOV [1] 64u5\n").unwrap().into();
+        for len in 0..image.len() {
+            assert!(ByteStream::try_from(image[..len].to_vec()).is_err());
+        }
+        assert_eq!(ByteStream::try_from(vec![0; 32]).unwrap_err(), FormatError::BadMagic);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod ops;
 pub mod types;
-pub mod data;
\ No newline at end of file
+pub mod data;
+pub mod encoding;
+pub mod format;
@@ -1,8 +1,10 @@
 
 use crate::bytecode::data::ByteData;
 use crate::builder::bytes::{Byte, ByteStream};
+use crate::bytecode::types::Types;
+use Operations::*;
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Operations {
     NOP = 0x00,
     //Arithmetic
@@ -31,16 +33,28 @@ pub enum Operations {
     JMP = 0x12,
     JZ = 0x13,
     JNZ = 0x14,
+    HALT = 0x22,
     //Memory
     LOAD = 0x17,
     STORE = 0x18,
     ALLOC = 0x1C,
     FREE = 0x1D,
     REALLOC = 0x1E,
+    //little endian loads and stores of a fixed width at base+offset
+    LOAD8 = 0x23,
+    LOAD16 = 0x24,
+    LOAD32 = 0x25,
+    LOAD64 = 0x26,
+    STORE8 = 0x27,
+    STORE16 = 0x28,
+    STORE32 = 0x29,
+    STORE64 = 0x2A,
     //IO
     WRITE = 0x19,
     READ = 0x1A,
     FLUSH = 0x1F,
+    READUNTIL = 0x20,
+    READLINE = 0x21,
 
     //registers
     MOV = 0x1B,
@@ -55,103 +69,77 @@ pub enum Operations {
     //accumulator
     WRACC = 0x67,
     REACC = 0x68,
-}
 
-impl From<Byte> for Operations {
-    fn from(byte: Byte) -> Self {
-        match *(byte.data) as u8 {
-            0x00 => Operations::NOP,
-            0x01 => Operations::ADD,
-            0x02 => Operations::SUB,
-            0x03 => Operations::MUL,
-            0x04 => Operations::DIV,
-            0x05 => Operations::MOD,
-            0x06 => Operations::AND,
-            0x07 => Operations::OR,
-            0x08 => Operations::XOR,
-            0x09 => Operations::NOT,
-            0x0A => Operations::EQ,
-            0x0B => Operations::NEQ,
-            0x0C => Operations::LT,
-            0x0D => Operations::GT,
-            0x0E => Operations::PUSH,
-            0x0F => Operations::POP,
-            0x10 => Operations::DUP,
-            0x11 => Operations::SWAP,
-            0x12 => Operations::JMP,
-            0x13 => Operations::JZ,
-            0x14 => Operations::JNZ,
-            0x17 => Operations::LOAD,
-            0x18 => Operations::STORE,
-            0x19 => Operations::WRITE,
-            0x1A => Operations::READ,
-            0x1B => Operations::MOV,
-            0x15 => Operations::INC,
-            0x16 => Operations::DEC,
-            0x1C => Operations::ALLOC,
-            0x1D => Operations::FREE,
-            0x1E => Operations::REALLOC,
-            0x1F => Operations::FLUSH,
-            0x64 => Operations::FUNC,
-            0x65 => Operations::RET,
-            0x66 => Operations::CALL,
-            0x67 => Operations::WRACC,
-            0x68 => Operations::REACC,
-            _ => panic!("Invalid opcode: {}", *(byte.data) as u8)
-        }
-    }
+    //host functions
+    NATIVE = 0x69,
 }
-impl From<u8> for Operations {
-    fn from(code: u8) -> Operations {
+
+impl TryFrom<u8> for Operations {
+    type Error = u8;
+    fn try_from(code: u8) -> Result<Operations, u8> {
         match code {
-            0x00 => Operations::NOP,
-            0x01 => Operations::ADD,
-            0x02 => Operations::SUB,
-            0x03 => Operations::MUL,
-            0x04 => Operations::DIV,
-            0x05 => Operations::MOD,
-            0x06 => Operations::AND,
-            0x07 => Operations::OR,
-            0x08 => Operations::XOR,
-            0x09 => Operations::NOT,
-            0x0A => Operations::EQ,
-            0x0B => Operations::NEQ,
-            0x0C => Operations::LT,
-            0x0D => Operations::GT,
-            0x0E => Operations::PUSH,
-            0x0F => Operations::POP,
-            0x10 => Operations::DUP,
-            0x11 => Operations::SWAP,
-            0x12 => Operations::JMP,
-            0x13 => Operations::JZ,
-            0x14 => Operations::JNZ,
-            0x17 => Operations::LOAD,
-            0x18 => Operations::STORE,
-            0x19 => Operations::WRITE,
-            0x1A => Operations::READ,
-            0x1B => Operations::MOV,
-            0x15 => Operations::INC,
-            0x16 => Operations::DEC,
-            0x1C => Operations::ALLOC,
-            0x1D => Operations::FREE,
-            0x1E => Operations::REALLOC,
-            0x1F => Operations::FLUSH,
-            0x64 => Operations::FUNC,
-            0x65 => Operations::RET,
-            0x66 => Operations::CALL,
-            0x67 => Operations::WRACC,
-            0x68 => Operations::REACC,
-            _ => panic!("Invalid opcode: {}", code)
+            0x00 => Ok(Operations::NOP),
+            0x01 => Ok(Operations::ADD),
+            0x02 => Ok(Operations::SUB),
+            0x03 => Ok(Operations::MUL),
+            0x04 => Ok(Operations::DIV),
+            0x05 => Ok(Operations::MOD),
+            0x06 => Ok(Operations::AND),
+            0x07 => Ok(Operations::OR),
+            0x08 => Ok(Operations::XOR),
+            0x09 => Ok(Operations::NOT),
+            0x0A => Ok(Operations::EQ),
+            0x0B => Ok(Operations::NEQ),
+            0x0C => Ok(Operations::LT),
+            0x0D => Ok(Operations::GT),
+            0x0E => Ok(Operations::PUSH),
+            0x0F => Ok(Operations::POP),
+            0x10 => Ok(Operations::DUP),
+            0x11 => Ok(Operations::SWAP),
+            0x12 => Ok(Operations::JMP),
+            0x13 => Ok(Operations::JZ),
+            0x14 => Ok(Operations::JNZ),
+            0x17 => Ok(Operations::LOAD),
+            0x18 => Ok(Operations::STORE),
+            0x19 => Ok(Operations::WRITE),
+            0x1A => Ok(Operations::READ),
+            0x1B => Ok(Operations::MOV),
+            0x15 => Ok(Operations::INC),
+            0x16 => Ok(Operations::DEC),
+            0x1C => Ok(Operations::ALLOC),
+            0x1D => Ok(Operations::FREE),
+            0x1E => Ok(Operations::REALLOC),
+            0x1F => Ok(Operations::FLUSH),
+            0x20 => Ok(Operations::READUNTIL),
+            0x21 => Ok(Operations::READLINE),
+            0x22 => Ok(Operations::HALT),
+            0x23 => Ok(Operations::LOAD8),
+            0x24 => Ok(Operations::LOAD16),
+            0x25 => Ok(Operations::LOAD32),
+            0x26 => Ok(Operations::LOAD64),
+            0x27 => Ok(Operations::STORE8),
+            0x28 => Ok(Operations::STORE16),
+            0x29 => Ok(Operations::STORE32),
+            0x2A => Ok(Operations::STORE64),
+            0x64 => Ok(Operations::FUNC),
+            0x65 => Ok(Operations::RET),
+            0x66 => Ok(Operations::CALL),
+            0x67 => Ok(Operations::WRACC),
+            0x68 => Ok(Operations::REACC),
+            0x69 => Ok(Operations::NATIVE),
+            _ => Err(code),
         }
     }
 }
 
 impl ByteData for Operations {
     fn get(&self) -> u8 {
-        self.clone() as u8
+        *self as u8
     }
     fn set(&mut self, data: u8) {
-        *self = unsafe { core::mem::transmute(data) };
+        if let Ok(op) = Operations::try_from(data) {
+            *self = op;
+        }
     }
 }
 
@@ -161,12 +149,13 @@ pub enum MathOperationSides {
     RegConst = 2,
     RegReg = 3,
 }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MathOpTypes {
     Signed = 0,
     Unsigned = 1,
     Float = 2,
 }
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArgType {
     Typed, Untyped, Dest, Func,
 }
@@ -179,6 +168,9 @@ pub const MATH_OP_ARGS: [ArgType; 2] = [
 pub const JMP_ARGS: [ArgType; 1] = [
     Typed
 ];
+pub const HALT_ARGS: [ArgType; 1] = [
+    Typed //Exit code
+];
 pub const REACC_ARGS: [ArgType; 1] = [
     Dest
 ];
@@ -213,6 +205,13 @@ pub const STORE_OP_ARGS: [ArgType; 2] = [
     Typed, Typed //Address, Data
 ];
 
+pub const LOAD_WIDTH_OP_ARGS: [ArgType; 3] = [
+    Dest, Typed, Typed //Reg, Base, Offset
+];
+pub const STORE_WIDTH_OP_ARGS: [ArgType; 3] = [
+    Typed, Typed, Typed //Base, Offset, Data
+];
+
 pub const IO_OUT_OP_ARGS: [ArgType; 2] = [
     Typed, Typed//Data, Size, Buffer
 ];
@@ -221,6 +220,10 @@ pub const IO_IN_OP_ARGS: [ArgType; 2] = [
     Typed, Typed //Address, Buffer, Size if not specified will run until oob
 ];
 
+pub const IO_IN_UNTIL_OP_ARGS: [ArgType; 3] = [
+    Typed, Typed, Typed //Address, Max size, Delimiter
+];
+
 pub const REG_OP_ARGS: [ArgType; 2] = [
     Dest, Typed //Reg, Value
 ];
@@ -234,9 +237,107 @@ pub const PUSH_OP_ARGS: [ArgType; 1] = [
 ];
 
 
-pub const INC_OP_ARGS: [ArgType; 1] = [
-    Dest //Reg
+pub const INC_OP_ARGS: [ArgType; 2] = [
+    Dest, Typed //Reg or Address, Amount
 ];
 pub const DEC_OP_ARGS: [ArgType; 1] = [
     Dest //Reg
-];
\ No newline at end of file
+];
+pub const POP_OP_ARGS: [ArgType; 1] = [
+    Dest //Reg
+];
+pub const NOT_OP_ARGS: [ArgType; 1] = [
+    Dest //Reg
+];
+pub const FUNC_OP_ARGS: [ArgType; 1] = [
+    Func //Name
+];
+pub const NATIVE_OP_ARGS: [ArgType; 1] = [
+    Func //Name given to Engine::register_native
+];
+
+impl ArgType {
+    //whether an operand of type tp can be given where this kind of argument is expected
+    pub fn accepts(&self, tp: Types) -> bool {
+        use Types::*;
+        match self {
+            Typed | Untyped => !matches!(tp, TypeOp),
+            Dest => matches!(tp, TypeReg),
+            Func => matches!(tp, TypeFunc | TypeJmp | TypeAddr | TypeU64),
+        }
+    }
+}
+
+impl Operations {
+    pub const ALL: [Operations; 49] = [
+        NOP, ADD, SUB, MUL, DIV, MOD, AND, OR, XOR, NOT, EQ, NEQ, LT, GT, PUSH, POP, DUP, SWAP,
+        JMP, JZ, JNZ, HALT, LOAD, STORE, LOAD8, LOAD16, LOAD32, LOAD64, STORE8, STORE16, STORE32,
+        STORE64, ALLOC, FREE, REALLOC, WRITE, READ, FLUSH, READUNTIL, READLINE, MOV, INC, DEC, FUNC,
+        RET, CALL, WRACC, REACC, NATIVE,
+    ];
+    //look an op up by the mnemonic mkasm prints for it
+    pub fn from_name(name: &str) -> Option<Operations> {
+        Operations::ALL
+            .iter()
+            .find(|op| format!("{:?}", op) == name)
+            .copied()
+    }
+    //the arguments the engine reads after this op, STORE is followed by as many extra Typed
+    //arguments as its second argument says and FUNC can be followed by its argument count
+    pub fn args(&self) -> &'static [ArgType] {
+        match self {
+            NOP | DUP | SWAP | FLUSH | RET => &[],
+            ADD | SUB | MUL | DIV | MOD | AND | OR | XOR => &MATH_OP_ARGS,
+            NOT => &NOT_OP_ARGS,
+            EQ | NEQ | LT | GT => &COMPARISON_OP_ARGS,
+            PUSH => &PUSH_OP_ARGS,
+            POP => &POP_OP_ARGS,
+            JMP => &JMP_ARGS,
+            JZ | JNZ => &CONTROL_FLOW_OP_ARGS,
+            HALT => &HALT_ARGS,
+            LOAD => &LOAD_OP_ARGS,
+            STORE => &STORE_OP_ARGS,
+            LOAD8 | LOAD16 | LOAD32 | LOAD64 => &LOAD_WIDTH_OP_ARGS,
+            STORE8 | STORE16 | STORE32 | STORE64 => &STORE_WIDTH_OP_ARGS,
+            ALLOC => &ALLOC_ARGS,
+            FREE => &FREE_ARGS,
+            REALLOC => &REALLOC_ARGS,
+            WRITE => &IO_OUT_OP_ARGS,
+            READ | READLINE => &IO_IN_OP_ARGS,
+            READUNTIL => &IO_IN_UNTIL_OP_ARGS,
+            MOV => &REG_OP_ARGS,
+            INC => &INC_OP_ARGS,
+            DEC => &DEC_OP_ARGS,
+            FUNC => &FUNC_OP_ARGS,
+            CALL => &CALL_OP_ARGS,
+            WRACC => &WRACC_ARGS,
+            REACC => &REACC_ARGS,
+            NATIVE => &NATIVE_OP_ARGS,
+        }
+    }
+    //bytes moved by the fixed width loads and stores
+    pub fn width(&self) -> Option<usize> {
+        match self {
+            LOAD8 | STORE8 => Some(1),
+            LOAD16 | STORE16 => Some(2),
+            LOAD32 | STORE32 => Some(4),
+            LOAD64 | STORE64 => Some(8),
+            _ => None,
+        }
+    }
+    //whether an operand of type tp is valid as argument index of this op
+    pub fn accepts(&self, index: usize, tp: Types) -> bool {
+        match (self, index) {
+            //INC can also increment a byte on the heap
+            (INC, 0) => matches!(tp, Types::TypeReg | Types::TypeAddr),
+            (FUNC, 0) | (NATIVE, 0) => matches!(tp, Types::TypeFunc),
+            //the optional argument count after a FUNC name
+            (FUNC, 1) => tp.is_literal(),
+            _ => match self.args().get(index) {
+                Some(arg) => arg.accepts(tp),
+                //extra STORE data
+                None => Typed.accepts(tp),
+            },
+        }
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, Copy)]
+use crate::bytecode::encoding::Encoding;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Types {
     TypeU8 = 0x00,
@@ -18,7 +20,10 @@ pub enum Types {
     DerefHeapReg = 0x0D,
     DerefStackReg = 0x0E,
     TypeJmp = 0x0F,
-    NoType
+    //pinned so files written before DerefIndexed was added keep their meaning
+    NoType = 0x10,
+    //an element on the heap at a register plus a scaled register plus a displacement, see Indexed
+    DerefIndexed = 0x11,
 }
 impl From<u8> for Types {
     fn from(byte: u8) -> Self {
@@ -39,7 +44,72 @@ impl From<u8> for Types {
             0x0D => Types::DerefHeapReg,
             0x0E => Types::DerefStackReg,
             0x0F => Types::TypeJmp,
+            0x11 => Types::DerefIndexed,
             _ => Types::NoType
         }
     }
-}
\ No newline at end of file
+}
+impl Types {
+    //how the data of this type is laid out in a .cbvm file
+    pub fn encoding(&self) -> Encoding {
+        use Types::*;
+        match self {
+            TypeOp => Encoding::Fixed(1),
+            TypeF32 => Encoding::Fixed(4),
+            TypeU64 | TypeI64 | TypeF64 => Encoding::Fixed(8),
+            TypeU128 | TypeI128 => Encoding::Fixed(16),
+            //displacement, base, index and scale
+            DerefIndexed => Encoding::Fixed(11),
+            TypeI8 => Encoding::SignedVarint,
+            TypeU8 | TypeAddr | TypeReg | TypeFunc | TypeJmp | DerefStack | DerefHeapReg
+            | DerefStackReg | NoType => Encoding::Varint,
+        }
+    }
+    //the bits of value an operand of this type keeps, only 128 bit literals and indexed operands
+    //are wider than 64 bits, so a negative i64 is stored the way the file format writes it
+    pub fn normalize(&self, value: u128) -> u128 {
+        use Types::*;
+        match self {
+            TypeU128 | TypeI128 | DerefIndexed => value,
+            _ => value as u64 as u128,
+        }
+    }
+    //constant values, as opposed to registers, stack slots and jump targets
+    pub fn is_literal(&self) -> bool {
+        use Types::*;
+        matches!(self, TypeU8 | TypeU64 | TypeI8 | TypeI64 | TypeU128 | TypeI128 | TypeAddr | NoType)
+    }
+}
+
+//the parts of a DerefIndexed operand, which reads scale bytes little endian from the heap at
+//regs[base] + regs[index] * scale + disp, so scale is both the stride and the element width
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Indexed {
+    pub base: u8,
+    pub index: u8,
+    pub scale: u8,
+    pub disp: i64,
+}
+
+impl Indexed {
+    //scales that are the width of a LOAD8 to LOAD64
+    pub const SCALES: [u8; 4] = [1, 2, 4, 8];
+    //the operand value, the displacement is the low 64 bits so it is what Byte::unwrap gives
+    pub fn pack(&self) -> u128 {
+        self.disp as u64 as u128
+            | (self.base as u128) << 64
+            | (self.index as u128) << 72
+            | (self.scale as u128) << 80
+    }
+    pub fn unpack(value: u128) -> Indexed {
+        Indexed {
+            base: (value >> 64) as u8,
+            index: (value >> 72) as u8,
+            scale: (value >> 80) as u8,
+            disp: value as u64 as i64,
+        }
+    }
+    pub fn valid_scale(&self) -> bool {
+        Indexed::SCALES.contains(&self.scale)
+    }
+}
@@ -0,0 +1,229 @@
+//arithmetic and comparisons that follow the types of their operands
+//registers, the accumulator and stack slots keep the type of the value put in them, values read
+//from the heap or set by the host are untyped and take the type of the other operand
+//floats are stored as their IEEE bits and 64 bit results are zero extended
+use crate::bytecode::ops::MathOpTypes;
+use crate::bytecode::types::Types::{self, *};
+use std::cmp::Ordering;
+
+//what happens when integer arithmetic does not fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowMode {
+    #[default]
+    Wrap,
+    Trap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumType {
+    pub class: MathOpTypes,
+    //32 or 64 for floats, 64 or 128 for integers
+    pub bits: u32,
+}
+
+impl NumType {
+    pub const UNSIGNED: NumType = NumType {
+        class: MathOpTypes::Unsigned,
+        bits: 64,
+    };
+    //the type an operand tagged tp carries, None for untyped registers and memory
+    pub fn of(tp: Types) -> Option<NumType> {
+        let (class, bits) = match tp {
+            TypeU8 | TypeU64 | TypeAddr | NoType => (MathOpTypes::Unsigned, 64),
+            TypeU128 => (MathOpTypes::Unsigned, 128),
+            TypeI8 | TypeI64 => (MathOpTypes::Signed, 64),
+            TypeI128 => (MathOpTypes::Signed, 128),
+            TypeF32 => (MathOpTypes::Float, 32),
+            TypeF64 => (MathOpTypes::Float, 64),
+            _ => return None,
+        };
+        Some(NumType { class, bits })
+    }
+    //floats win over signed, signed over unsigned, and integers use the wider of the two widths
+    pub fn combine(left: Option<NumType>, right: Option<NumType>) -> NumType {
+        let rank = |t: &NumType| match t.class {
+            MathOpTypes::Unsigned => 0,
+            MathOpTypes::Signed => 1,
+            MathOpTypes::Float => 2,
+        };
+        match (left, right) {
+            (Some(l), Some(r)) => {
+                let (high, low) = if rank(&l) >= rank(&r) { (l, r) } else { (r, l) };
+                match (high.class, low.class) {
+                    (MathOpTypes::Float, MathOpTypes::Float) => NumType {
+                        class: high.class,
+                        bits: high.bits.max(low.bits),
+                    },
+                    (MathOpTypes::Float, _) => high,
+                    _ => NumType {
+                        class: high.class,
+                        bits: high.bits.max(low.bits),
+                    },
+                }
+            }
+            (Some(t), None) | (None, Some(t)) => t,
+            (None, None) => NumType::UNSIGNED,
+        }
+    }
+}
+
+//reinterpret a value of type from as type to, so 2 + 1.5 is done as 2.0 + 1.5
+pub fn convert(value: u128, from: NumType, to: NumType) -> u128 {
+    if from == to {
+        return value;
+    }
+    let float = |value: f64| match to.bits {
+        32 => (value as f32).to_bits() as u128,
+        _ => value.to_bits() as u128,
+    };
+    match (from.class, to.class) {
+        (MathOpTypes::Float, MathOpTypes::Float) => match from.bits {
+            32 => float(f32::from_bits(value as u32) as f64),
+            _ => float(f64::from_bits(value as u64)),
+        },
+        (MathOpTypes::Signed, MathOpTypes::Float) => float(signed(value, from.bits) as f64),
+        (MathOpTypes::Unsigned, MathOpTypes::Float) => float(unsigned(value, from.bits) as f64),
+        //widening keeps the sign of signed values
+        (MathOpTypes::Signed, _) => signed(value, from.bits) as u128,
+        _ => unsigned(value, from.bits),
+    }
+}
+
+fn signed(value: u128, bits: u32) -> i128 {
+    match bits {
+        128 => value as i128,
+        _ => value as u64 as i64 as i128,
+    }
+}
+
+fn unsigned(value: u128, bits: u32) -> u128 {
+    match bits {
+        128 => value,
+        _ => value as u64 as u128,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithError {
+    DivisionByZero,
+    Overflow,
+}
+
+//integer ops, the same for every width and signedness
+macro_rules! integer {
+    ($op:expr, $left:expr, $right:expr, $mode:expr) => {{
+        let (left, right) = ($left, $right);
+        if right == 0 && matches!($op, ArithOp::Div | ArithOp::Mod) {
+            return Err(ArithError::DivisionByZero);
+        }
+        let (value, overflowed) = match $op {
+            ArithOp::Add => left.overflowing_add(right),
+            ArithOp::Sub => left.overflowing_sub(right),
+            ArithOp::Mul => left.overflowing_mul(right),
+            ArithOp::Div => left.overflowing_div(right),
+            ArithOp::Mod => left.overflowing_rem(right),
+        };
+        match (overflowed, $mode) {
+            (true, OverflowMode::Trap) => return Err(ArithError::Overflow),
+            _ => value,
+        }
+    }};
+}
+
+pub fn arith(op: ArithOp, tp: NumType, left: u128, right: u128, mode: OverflowMode) -> Result<u128, ArithError> {
+    Ok(match (tp.class, tp.bits) {
+        (MathOpTypes::Float, 32) => {
+            let (l, r) = (f32::from_bits(left as u32), f32::from_bits(right as u32));
+            (float(op, l as f64, r as f64) as f32).to_bits() as u128
+        }
+        (MathOpTypes::Float, _) => float(op, f64::from_bits(left as u64), f64::from_bits(right as u64)).to_bits() as u128,
+        (MathOpTypes::Signed, 128) => integer!(op, left as i128, right as i128, mode) as u128,
+        (MathOpTypes::Signed, _) => integer!(op, left as i64, right as i64, mode) as u64 as u128,
+        (MathOpTypes::Unsigned, 128) => integer!(op, left, right, mode),
+        (MathOpTypes::Unsigned, _) => integer!(op, left as u64, right as u64, mode) as u128,
+    })
+}
+
+//floats never trap, dividing by zero gives an infinity or NaN as IEEE says
+fn float(op: ArithOp, left: f64, right: f64) -> f64 {
+    match op {
+        ArithOp::Add => left + right,
+        ArithOp::Sub => left - right,
+        ArithOp::Mul => left * right,
+        ArithOp::Div => left / right,
+        ArithOp::Mod => left % right,
+    }
+}
+
+//None when either side is NaN, every ordered comparison is then false
+pub fn compare(tp: NumType, left: u128, right: u128) -> Option<Ordering> {
+    match (tp.class, tp.bits) {
+        (MathOpTypes::Float, 32) => f32::from_bits(left as u32).partial_cmp(&f32::from_bits(right as u32)),
+        (MathOpTypes::Float, _) => f64::from_bits(left as u64).partial_cmp(&f64::from_bits(right as u64)),
+        (MathOpTypes::Signed, bits) => Some(signed(left, bits).cmp(&signed(right, bits))),
+        (MathOpTypes::Unsigned, bits) => Some(unsigned(left, bits).cmp(&unsigned(right, bits))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+
+    fn run(asm: &str) -> Engine {
+        let mut engine = Engine::new();
+        engine.run(crate::asm::rvasm(asm).expect("program should assemble")).unwrap();
+        engine
+    }
+
+    fn f32_bits(value: f32) -> u128 {
+        value.to_bits() as u128
+    }
+
+    #[test]
+    fn float_registers_add_as_floats() {
+        //1.5 + 2.0
+        let engine = run("MOV [1] 32f3fc00000\nMOV [2] 32f40000000\nADD [1] [2]\n");
+        assert_eq!(engine.accumulator(), f32_bits(3.5));
+        //an integer register is converted to the float type of the other
+        let engine = run("MOV [1] 32f3fc00000\nMOV [2] 64i2\nMUL [1] [2]\n");
+        assert_eq!(engine.accumulator(), f32_bits(3.0));
+        let engine = run("MOV [1] 32f3fc00000\nMOV [2] 32f40000000\nLT [2] [1]\n");
+        assert_eq!(engine.accumulator(), 0);
+    }
+
+    #[test]
+    fn signed_registers_compare_and_divide_as_signed() {
+        let minus_one = "MOV [1] 64iffffffffffffffff\nMOV [2] 64i1\n";
+        assert_eq!(run(&format!("{}LT [1] [2]\n", minus_one)).accumulator(), 1);
+        assert_eq!(run(&format!("{}GT [1] [2]\n", minus_one)).accumulator(), 0);
+        let engine = run(&format!("{}MOV [2] 64i2\nDIV [1] [2]\n", minus_one));
+        //-1 / 2 rounds towards zero
+        assert_eq!(engine.accumulator(), 0);
+        //widening to 128 bits keeps the sign
+        let engine = run(&format!("{}ADD [1] 128u1\n", minus_one));
+        assert_eq!(engine.accumulator(), 0);
+    }
+
+    #[test]
+    fn types_follow_values_through_the_stack_and_accumulator() {
+        let engine = run(concat!(
+            "MOV [1] 32f3fc00000\nMOV [2] 32f40000000\n",
+            "PUSH [1]\nPOP [3]\nADD [3] [2]\nREACC [4]\nADD [4] [2]\n",
+        ));
+        assert_eq!(engine.accumulator(), f32_bits(5.5));
+        let engine = run("MOV [1] 64iffffffffffffffff\nPUSH [1]\nDUP\nLT (1) 64u0\n");
+        assert_eq!(engine.accumulator(), 1);
+        //values from the heap carry no type, so they are unsigned unless the other side says
+        let engine = run("ALLOC [1] 64u1\nSTORE [1] 64u1 64uff\nMOV [2] h1\nLT [2] 64u1\n");
+        assert_eq!(engine.accumulator(), 0);
+    }
+}
@@ -1,8 +1,135 @@
-
 extern crate alloc;
+use super::arith::NumType;
 use alloc::vec::Vec;
 
-pub type CallStack = Vec<FnCall>;
-pub struct FnCall {
-    pub ret: u8
-}
\ No newline at end of file
+//how deep calls can nest before CALL faults with a stack overflow
+pub const MAX_FRAMES: usize = 1024;
+
+//one active call, pushed by CALL and popped by RET
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    //index in the bytestream to continue from after RET
+    pub ret: usize,
+    //where the function body started, for backtraces
+    pub target: usize,
+    //the argument registers at the call, as many as the function declares
+    pub args: Vec<u128>,
+    //stack pointer when the call was made, RET drops anything the function left above it
+    //so locals do not leak into the caller
+    pub base: usize,
+    //registers to put back on RET, and the values and types they held at the call
+    pub saved: Vec<(usize, u128, Option<NumType>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    limit: usize,
+}
+
+impl CallStack {
+    pub fn new(limit: usize) -> CallStack {
+        CallStack {
+            frames: Vec::new(),
+            limit,
+        }
+    }
+    //false when the call would nest deeper than the limit
+    pub fn push(&mut self, frame: Frame) -> bool {
+        if self.frames.len() >= self.limit {
+            return false;
+        }
+        self.frames.push(frame);
+        true
+    }
+    pub fn pop(&mut self) -> Option<Frame> {
+        self.frames.pop()
+    }
+    //the frame of the function currently running
+    pub fn current(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+    //outermost call first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Frame> {
+        self.frames.iter()
+    }
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self::new(MAX_FRAMES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::rvasm;
+    use crate::engine::capture::{run_captured, run_captured_with};
+    use crate::engine::{EngineConfig, ExitStatus, Limit, VmError};
+
+    //counts r1 down from 0x64 to 0 with one nested call per step
+    const COUNTDOWN: &str = "JMP &main
+.func down()
+    SUB [1] 64u1
+    REACC [1]
+    JZ &done
+    CALL :down
+done:
+    RET
+main:
+    MOV [1] 64u64
+    CALL :down
+";
+
+    #[test]
+    fn calls_return_to_addresses_past_255() {
+        let nops = "NOP\n".repeat(300);
+        let asm = format!(
+            "JMP &main\n.func seven()\n    WRACC 64u7\n    RET\nmain:\n{}CALL :seven\nREACC [1]\n",
+            nops
+        );
+        let output = run_captured(rvasm(&asm).unwrap(), b"");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!(output.registers[1], 7);
+        //and to functions that start past 255
+        let asm = format!(
+            "CALL :far\nREACC [1]\nJMP &end\n{}.func far()\n    WRACC 64u9\n    RET\nend:\n",
+            nops
+        );
+        let output = run_captured(rvasm(&asm).unwrap(), b"");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!(output.registers[1], 9);
+    }
+
+    #[test]
+    fn calls_nest_until_the_depth_limit() {
+        let output = run_captured(rvasm(COUNTDOWN).unwrap(), b"");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!(output.registers[1], 0);
+        let config = EngineConfig {
+            max_call_depth: 50,
+            ..EngineConfig::default()
+        };
+        let output = run_captured_with(rvasm(COUNTDOWN).unwrap(), b"", config);
+        assert!(matches!(
+            output.status,
+            Err(VmError::LimitExceeded { limit: Limit::CallDepth, .. })
+        ));
+        assert_eq!(output.registers[1], 100 - 50);
+    }
+
+    #[test]
+    fn ret_without_a_call_underflows() {
+        let output = run_captured(rvasm("NOP\nRET\n").unwrap(), b"");
+        assert_eq!(output.status, Err(VmError::StackUnderflow { ip: 1 }));
+    }
+}
@@ -0,0 +1,64 @@
+//running a program against in-memory input and handing back everything it did, for tests, tools
+//and hosts that want the output as a value
+use crate::builder::bytes::ByteStream;
+use crate::engine::{Engine, EngineConfig, ExitStatus, MemoryIo, VmError, REGISTER_COUNT};
+
+//everything a program did when run with run_captured
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunOutput {
+    pub stdout: Vec<u8>,
+    pub status: Result<ExitStatus, VmError>,
+    //instructions executed, including the one that faulted
+    pub instructions: u64,
+    pub registers: [u128; REGISTER_COUNT],
+    pub accumulator: u128,
+}
+
+//run a program without touching the real stdin and stdout, for tests and tools
+pub fn run_captured(bytes: ByteStream, stdin: &[u8]) -> RunOutput {
+    run_captured_with(bytes, stdin, EngineConfig::default())
+}
+
+pub fn run_captured_with(bytes: ByteStream, stdin: &[u8], config: EngineConfig) -> RunOutput {
+    let io = MemoryIo::new(stdin);
+    let mut engine = Engine::with_config(config);
+    engine.set_io(Box::new(io.clone()));
+    let status = engine.run(bytes);
+    RunOutput {
+        stdout: io.take_output(),
+        status,
+        instructions: engine.instructions(),
+        registers: engine.regs.data,
+        accumulator: engine.accumulator(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::rvasm;
+
+    #[test]
+    fn output_and_final_state_come_back_as_values() {
+        let program = rvasm("ALLOC [1] 64u8\nREAD [1] 64u8\nWRITE [1] 64u3\nFLUSH\n").unwrap();
+        let output = run_captured(program, b"hey there");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!(output.stdout, b"hey");
+        assert_eq!(output.accumulator, 8);
+        assert_eq!(output.instructions, 4);
+        assert_eq!(output.registers[1], 0);
+    }
+
+    #[test]
+    fn output_before_a_fault_is_kept() {
+        let program = rvasm(concat!(
+            "ALLOC [1] 64u2\nSTORE [1] 64u2 64u6f 64u6b\nWRITE [1] 64u2\n",
+            "FREE [1]\nFREE [1]\n",
+        ));
+        let output = run_captured(program.unwrap(), b"");
+        assert_eq!(output.stdout, b"ok");
+        assert!(matches!(output.status, Err(VmError::HeapFault { .. })));
+        //the faulting FREE is counted
+        assert_eq!(output.instructions, 5);
+    }
+}
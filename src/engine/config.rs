@@ -0,0 +1,121 @@
+//resource limits for an Engine, anything a program can use up is capped here
+use crate::engine::callstack::MAX_FRAMES;
+use crate::engine::memory::STACK_SLOTS;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EngineConfig {
+    //bytes of heap programs can allocate from
+    pub heap_size: usize,
+    //slots on the value stack
+    pub stack_slots: usize,
+    //how deep CALL can nest
+    pub max_call_depth: usize,
+    //instructions to execute before stopping, None for no limit
+    pub max_instructions: Option<u64>,
+    //bytes WRITE can produce in total, None for no limit
+    pub max_output: Option<usize>,
+    //fuel to start with, None to run unmetered
+    pub fuel: Option<u64>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        EngineConfig {
+            heap_size: 8192,
+            stack_slots: STACK_SLOTS,
+            max_call_depth: MAX_FRAMES,
+            max_instructions: None,
+            max_output: None,
+            fuel: None,
+        }
+    }
+}
+
+//which limit of the config a program ran into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    StackSlots,
+    CallDepth,
+    Instructions,
+    Output,
+    //heap_size, when an allocation cannot find room
+    Heap,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::StackSlots => write!(f, "stack size"),
+            Limit::CallDepth => write!(f, "call depth"),
+            Limit::Instructions => write!(f, "instruction budget"),
+            Limit::Output => write!(f, "output size"),
+            Limit::Heap => write!(f, "heap size"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::rvasm;
+    use crate::engine::capture::{run_captured_with, RunOutput};
+    use crate::engine::{ExitStatus, VmError};
+
+    fn run(asm: &str, config: EngineConfig) -> RunOutput {
+        run_captured_with(rvasm(asm).unwrap(), b"", config)
+    }
+
+    fn limit(output: &RunOutput) -> Option<Limit> {
+        match output.status {
+            Err(VmError::LimitExceeded { limit, .. }) => Some(limit),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn heap_size_caps_allocations() {
+        let config = EngineConfig {
+            heap_size: 16,
+            ..EngineConfig::default()
+        };
+        let output = run("ALLOC [1] 64u10\n", config.clone());
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!(limit(&run("ALLOC [1] 64u11\n", config)), Some(Limit::Heap));
+    }
+
+    #[test]
+    fn stack_slots_cap_pushes() {
+        let config = EngineConfig {
+            stack_slots: 2,
+            ..EngineConfig::default()
+        };
+        let output = run("PUSH 64u1\nPUSH 64u2\nPUSH 64u3\n", config);
+        assert_eq!(limit(&output), Some(Limit::StackSlots));
+        assert_eq!(output.instructions, 3);
+    }
+
+    #[test]
+    fn max_instructions_stops_endless_loops() {
+        let config = EngineConfig {
+            max_instructions: Some(10),
+            ..EngineConfig::default()
+        };
+        let output = run("top:\nNOP\nJMP &top\n", config);
+        assert_eq!(limit(&output), Some(Limit::Instructions));
+        assert_eq!(output.instructions, 10);
+    }
+
+    #[test]
+    fn max_output_caps_what_is_written() {
+        let config = EngineConfig {
+            max_output: Some(3),
+            ..EngineConfig::default()
+        };
+        let asm = "ALLOC [1] 64u2\nSTORE [1] 64u2 64u68 64u69\nWRITE [1] 64u2\nWRITE [1] 64u2\n";
+        let output = run(asm, config);
+        assert_eq!(limit(&output), Some(Limit::Output));
+        //the write that would go over is not done at all
+        assert_eq!(output.stdout, b"hi");
+    }
+}
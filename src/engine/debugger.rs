@@ -0,0 +1,306 @@
+//interactive debugger driven by text commands, used by `cbvm debug`
+use super::{Engine, ExitStatus, StepResult, VmError, ARG_REGS};
+use crate::asm;
+use crate::builder::bytes::ByteStream;
+use crate::bytecode::format::{func_name, symbols};
+use crate::bytecode::ops::Operations;
+use crate::bytecode::types::Types;
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+commands:
+  s, step [n]          execute the next n instructions (default 1)
+  n, next              step, running over a CALL until it returns
+  c, continue          run until a breakpoint or the end of the program
+  b, break <ip|label>  set a breakpoint at an instruction index or function label
+  d, delete <ip>       remove a breakpoint
+  breaks               list breakpoints
+  l, list              show the current instruction
+  r, regs              print all registers
+  acc                  print the accumulator
+  stack                print the stack, top first
+  bt, callstack        print the callstack
+  heap <addr> <len>    dump a range of the heap
+  heap                 print heap usage
+  q, quit              stop debugging
+an empty line repeats the last command";
+
+enum Outcome {
+    Prompt,
+    Done(Result<ExitStatus, VmError>),
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<usize>,
+    last: String,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+    //read commands from input until the program ends or the user quits
+    pub fn repl<R: BufRead, W: Write>(
+        &mut self,
+        engine: &mut Engine,
+        mut input: R,
+        mut out: W,
+    ) -> Result<ExitStatus, VmError> {
+        if self.show(engine, &mut out).is_err() {
+            return Ok(ExitStatus::Stopped);
+        }
+        loop {
+            let mut line = String::new();
+            let read = write!(out, "(cbvm) ")
+                .and_then(|_| out.flush())
+                .and_then(|_| input.read_line(&mut line));
+            match read {
+                Ok(0) | Err(_) => return Ok(ExitStatus::Stopped),
+                Ok(_) => (),
+            }
+            let line = match line.trim() {
+                "" => self.last.clone(),
+                line => line.to_string(),
+            };
+            self.last = line.clone();
+            match self.command(engine, &line, &mut out) {
+                Ok(Outcome::Prompt) => (),
+                Ok(Outcome::Done(status)) => return status,
+                Err(_) => return Ok(ExitStatus::Stopped),
+            }
+        }
+    }
+    fn command<W: Write>(&mut self, engine: &mut Engine, line: &str, out: &mut W) -> io::Result<Outcome> {
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or("");
+        let args: Vec<&str> = words.collect();
+        match cmd {
+            "s" | "step" => {
+                let mut count = match args.first() {
+                    Some(n) => match parse_number(n) {
+                        Some(n) => n,
+                        None => {
+                            writeln!(out, "not a number: {}", n)?;
+                            return Ok(Outcome::Prompt);
+                        }
+                    },
+                    None => 1,
+                };
+                return self.resume(engine, out, |_| {
+                    count = count.saturating_sub(1);
+                    count == 0
+                });
+            }
+            "n" | "next" => {
+                let depth = engine.callstack.len();
+                return self.resume(engine, out, |engine| engine.callstack.len() <= depth);
+            }
+            "c" | "continue" => return self.resume(engine, out, |_| false),
+            "b" | "break" => match args.first() {
+                Some(target) => match self.locate(engine, target) {
+                    Some(ip) => {
+                        if !self.breakpoints.contains(&ip) {
+                            self.breakpoints.push(ip);
+                        }
+                        writeln!(out, "breakpoint set at {}", ip)?;
+                    }
+                    None => writeln!(out, "no instruction or function called {}", target)?,
+                },
+                None => writeln!(out, "usage: break <ip|label>")?,
+            },
+            "d" | "delete" => match args.first().and_then(|n| parse_number(n)) {
+                Some(ip) => match self.breakpoints.iter().position(|&b| b == ip) {
+                    Some(pos) => {
+                        self.breakpoints.remove(pos);
+                        writeln!(out, "breakpoint at {} removed", ip)?;
+                    }
+                    None => writeln!(out, "no breakpoint at {}", ip)?,
+                },
+                None => writeln!(out, "usage: delete <ip>")?,
+            },
+            "breaks" => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "no breakpoints")?;
+                }
+                for ip in &self.breakpoints {
+                    writeln!(out, "  {}{}", ip, function_at(engine, *ip).map(|f| format!(" in :{}", f)).unwrap_or_default())?;
+                }
+            }
+            "l" | "list" => self.show(engine, out)?,
+            "r" | "regs" => {
+                for (i, row) in engine.regs.data.chunks(4).enumerate() {
+                    let row: Vec<String> = row
+                        .iter()
+                        .enumerate()
+                        .map(|(j, value)| format!("r{:02} {:#018x}", i * 4 + j, value))
+                        .collect();
+                    writeln!(out, "{}", row.join("  "))?;
+                }
+            }
+            "acc" => writeln!(out, "acc {:#018x} ({})", engine.accumulator, engine.accumulator)?,
+            "stack" => {
+                let contents = engine.stack.contents();
+                if contents.is_empty() {
+                    writeln!(out, "stack is empty")?;
+                }
+                for (offset, value) in contents.iter().rev().enumerate() {
+                    writeln!(out, "  ({:x}) {:#018x}", offset + 1, value)?;
+                }
+            }
+            "bt" | "callstack" => {
+                //innermost first, each line is where that function is and the arguments it was called with
+                let frames: Vec<_> = engine.callstack.iter().rev().collect();
+                for depth in 0..=frames.len() {
+                    let ip = match depth {
+                        0 => engine.ip,
+                        _ => frames[depth - 1].ret,
+                    };
+                    match frames.get(depth) {
+                        Some(frame) => {
+                            let args: Vec<String> = ARG_REGS
+                                .zip(&frame.args)
+                                .map(|(reg, value)| format!("r{}={}", reg, value))
+                                .collect();
+                            let name = function_starting(engine, frame.target).unwrap_or_else(|| format!("#{}", frame.target));
+                            writeln!(out, "#{}  ip {} in :{}({}), stack base {}", depth, ip, name, args.join(", "), frame.base)?;
+                        }
+                        None => writeln!(out, "#{}  ip {}", depth, ip)?,
+                    }
+                }
+            }
+            "heap" => match (args.first().and_then(|a| parse_number(a)), args.get(1).and_then(|l| parse_number(l))) {
+                (Some(addr), Some(len)) => match engine.heap.read(addr, len) {
+                    Ok(data) => {
+                        for (row, chunk) in data.chunks(16).enumerate() {
+                            let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                            writeln!(out, "{:#06x}  {}", addr + row * 16, bytes.join(" "))?;
+                        }
+                    }
+                    Err(fault) => writeln!(out, "cannot read {} bytes at {}", len, fault.addr)?,
+                },
+                _ if args.is_empty() => {
+                    let stats = engine.heap.stats();
+                    writeln!(out, "{} of {} bytes used in {} blocks, peak {}", stats.used, stats.size, stats.allocated_blocks, stats.peak)?;
+                    writeln!(out, "{} free blocks, largest {}, fragmentation {:.2}", stats.free_blocks, stats.largest_free, stats.fragmentation())?;
+                }
+                _ => writeln!(out, "usage: heap [<addr> <len>]")?,
+            },
+            "q" | "quit" => return Ok(Outcome::Done(Ok(ExitStatus::Stopped))),
+            "h" | "help" => writeln!(out, "{}", HELP)?,
+            _ => writeln!(out, "unknown command {}, try help", cmd)?,
+        }
+        Ok(Outcome::Prompt)
+    }
+    //step until stop returns true, a breakpoint is reached or the program ends
+    fn resume<W: Write>(
+        &self,
+        engine: &mut Engine,
+        out: &mut W,
+        mut stop: impl FnMut(&Engine) -> bool,
+    ) -> io::Result<Outcome> {
+        if engine.finished() {
+            writeln!(out, "program finished")?;
+            return Ok(Outcome::Done(Ok(engine.exit_status())));
+        }
+        loop {
+            match engine.step() {
+                StepResult::Ran => (),
+                StepResult::Exited(ExitStatus::Finished) => {
+                    writeln!(out, "program finished")?;
+                    return Ok(Outcome::Done(Ok(ExitStatus::Finished)));
+                }
+                StepResult::Exited(ExitStatus::Halted(code)) => {
+                    writeln!(out, "program halted with code {}", code)?;
+                    return Ok(Outcome::Done(Ok(ExitStatus::Halted(code))));
+                }
+                StepResult::Exited(status) => {
+                    writeln!(out, "program stopped: {:?}", status)?;
+                    return Ok(Outcome::Done(Ok(status)));
+                }
+                StepResult::Fault(err) => {
+                    writeln!(out, "{}", err)?;
+                    return Ok(Outcome::Done(Err(err)));
+                }
+            }
+            if self.breakpoints.contains(&engine.ip) {
+                writeln!(out, "breakpoint at {}", engine.ip)?;
+                break;
+            }
+            if stop(engine) {
+                break;
+            }
+        }
+        self.show(engine, out)?;
+        Ok(Outcome::Prompt)
+    }
+    //print the instruction at ip
+    fn show<W: Write>(&self, engine: &Engine, out: &mut W) -> io::Result<()> {
+        if engine.finished() {
+            return writeln!(out, "at end of program");
+        }
+        let bytes = &engine.data.bytes;
+        let end = bytes[engine.ip + 1..]
+            .iter()
+            .position(|b| matches!(b.tp, Types::TypeOp))
+            .map_or(bytes.len(), |n| engine.ip + 1 + n);
+        let text = asm::mkasm(ByteStream::from(&bytes[engine.ip..end]));
+        writeln!(out, "=> {}: {}{}", engine.ip, text.trim(), in_function(engine, engine.ip))
+    }
+    //a breakpoint target is either an instruction index or the name of a FUNC label
+    fn locate(&self, engine: &Engine, target: &str) -> Option<usize> {
+        let name = target.trim_start_matches(':');
+        let label = engine
+            .functions
+            .iter()
+            .find(|(packed, _)| func_name(**packed) == name)
+            .map(|(_, &start)| start);
+        label.or_else(|| parse_number(target).filter(|&ip| ip < engine.data.bytes.len()))
+    }
+}
+
+//name of the function whose body contains ip, if any. a body ends at its last RET before the next
+//FUNC, so code placed after a function is not shown as part of it
+fn function_at(engine: &Engine, ip: usize) -> Option<String> {
+    let symbols = symbols(&engine.data);
+    let symbol = symbols
+        .iter()
+        .filter(|symbol| symbol.body() <= ip)
+        .max_by_key(|symbol| symbol.body())?;
+    let next = symbols
+        .iter()
+        .map(|symbol| symbol.index)
+        .filter(|&index| index > symbol.index)
+        .min()
+        .unwrap_or(engine.data.bytes.len());
+    let last_ret = (symbol.body()..next).rev().find(|&at| {
+        let byte = &engine.data.bytes[at];
+        byte.tp == Types::TypeOp && byte.unwrap() == Operations::RET as u64
+    })?;
+    match ip <= last_ret {
+        true => Some(symbol.name.clone()),
+        false => None,
+    }
+}
+
+fn function_starting(engine: &Engine, start: usize) -> Option<String> {
+    engine
+        .functions
+        .iter()
+        .find(|(_, &body)| body == start)
+        .map(|(packed, _)| func_name(*packed))
+}
+
+fn in_function(engine: &Engine, ip: usize) -> String {
+    function_at(engine, ip)
+        .map(|name| format!(" in :{}", name))
+        .unwrap_or_default()
+}
+
+//decimal, or hex with a 0x prefix
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
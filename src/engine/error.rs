@@ -0,0 +1,235 @@
+use crate::bytecode::format::FormatError;
+use crate::engine::config::Limit;
+use crate::engine::memory::{HeapFault, HeapOp, StackFault};
+use crate::verify::VerifyError;
+use std::fmt;
+
+//how a program finished when it did not fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    //ran off the end of the bytestream
+    Finished,
+    //the debugger was told to quit before the program finished
+    Stopped,
+    //there was not enough fuel for the next instruction, refuel and resume to carry on
+    OutOfFuel,
+    //run_for executed as many instructions as it was asked to
+    Paused,
+    //the program ran HALT with this exit code, always within 0..=MAX_HALT_CODE
+    Halted(i32),
+}
+
+//programs own the exit codes 0..=MAX_HALT_CODE, HALT with anything larger faults
+//the vm's own codes start at VM_EXIT_CODES so a program can never be mistaken for a fault
+//and neither clashes with the 128+signal codes shells use for killed processes
+pub const MAX_HALT_CODE: i32 = 127;
+pub const VM_EXIT_CODES: i32 = 200;
+const _: () = assert!(MAX_HALT_CODE < VM_EXIT_CODES);
+
+impl ExitStatus {
+    //the process status the cbvm binary exits with, faults use VmError::exit_code
+    pub fn code(&self) -> i32 {
+        match *self {
+            ExitStatus::Finished | ExitStatus::Stopped | ExitStatus::Paused => 0,
+            ExitStatus::Halted(code) => code,
+            ExitStatus::OutOfFuel => VM_EXIT_CODES + 14,
+        }
+    }
+}
+
+//what a single Engine::step did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepResult {
+    //an instruction ran and there is more of the program left
+    Ran,
+    //nothing more will run until the engine is resumed, or at all if the program finished
+    Exited(ExitStatus),
+    //the instruction faulted
+    Fault(VmError),
+}
+
+//every error carries the instruction pointer of the op that caused it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmError {
+    InvalidOpcode { ip: usize, opcode: u64 },
+    MissingOperand { ip: usize },
+    BadRegister { ip: usize, reg: usize },
+    HeapFault { ip: usize, addr: usize, op: HeapOp },
+    StackUnderflow { ip: usize },
+    //the program used more of something than the EngineConfig allows
+    LimitExceeded { ip: usize, limit: Limit },
+    BadJump { ip: usize, target: usize },
+    DivisionByZero { ip: usize },
+    //integer arithmetic overflowed while overflow trapping was on
+    Overflow { ip: usize },
+    //an indexed operand had a scale that is not 1, 2, 4 or 8
+    BadScale { ip: usize, scale: u8 },
+    //HALT was given a code outside 0..=MAX_HALT_CODE
+    BadExitCode { ip: usize, code: u128 },
+    //NATIVE named a function the host never registered
+    UnknownNative { ip: usize, name: String },
+    //a native returned an error
+    Native { ip: usize, name: String, message: String },
+    //reading input or writing output failed
+    Io { ip: usize, message: String },
+    //the program was rejected by the verifier before it started
+    Unverified(Vec<VerifyError>),
+    //the bytes given to cbvm::run are not a valid .cbvm image
+    Format(FormatError),
+}
+
+impl VmError {
+    pub fn ip(&self) -> usize {
+        match *self {
+            VmError::InvalidOpcode { ip, .. }
+            | VmError::MissingOperand { ip }
+            | VmError::BadRegister { ip, .. }
+            | VmError::HeapFault { ip, .. }
+            | VmError::StackUnderflow { ip }
+            | VmError::LimitExceeded { ip, .. }
+            | VmError::UnknownNative { ip, .. }
+            | VmError::Native { ip, .. }
+            | VmError::Io { ip, .. }
+            | VmError::BadJump { ip, .. }
+            | VmError::BadScale { ip, .. }
+            | VmError::BadExitCode { ip, .. }
+            | VmError::DivisionByZero { ip }
+            | VmError::Overflow { ip } => ip,
+            VmError::Unverified(ref errors) => errors.first().map_or(0, |err| err.at()),
+            VmError::Format(_) => 0,
+        }
+    }
+    //a distinct process status for each kind of fault, all at or above VM_EXIT_CODES
+    pub fn exit_code(&self) -> i32 {
+        VM_EXIT_CODES
+            + match self {
+                VmError::InvalidOpcode { .. } => 1,
+                VmError::MissingOperand { .. } => 2,
+                VmError::BadRegister { .. } => 3,
+                VmError::HeapFault { .. } => 4,
+                VmError::StackUnderflow { .. } => 5,
+                VmError::LimitExceeded { .. } => 6,
+                VmError::BadJump { .. } => 7,
+                VmError::DivisionByZero { .. } => 8,
+                VmError::Overflow { .. } => 9,
+                VmError::UnknownNative { .. } => 10,
+                VmError::Native { .. } => 11,
+                VmError::Io { .. } => 12,
+                VmError::Unverified(_) => 13,
+                VmError::BadScale { .. } => 15,
+                VmError::Format(_) => 16,
+                VmError::BadExitCode { .. } => 17,
+            }
+    }
+    pub(crate) fn heap(ip: usize, fault: HeapFault) -> VmError {
+        VmError::HeapFault {
+            ip,
+            addr: fault.addr,
+            op: fault.op,
+        }
+    }
+    pub(crate) fn io(ip: usize, err: std::io::Error) -> VmError {
+        VmError::Io {
+            ip,
+            message: err.to_string(),
+        }
+    }
+    pub(crate) fn stack(ip: usize, fault: StackFault) -> VmError {
+        match fault {
+            StackFault::Overflow => VmError::LimitExceeded {
+                ip,
+                limit: Limit::StackSlots,
+            },
+            StackFault::Underflow => VmError::StackUnderflow { ip },
+        }
+    }
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::InvalidOpcode { opcode, .. } => write!(f, "invalid opcode {:#x}", opcode)?,
+            VmError::MissingOperand { .. } => write!(f, "program ended while reading operands")?,
+            VmError::BadRegister { reg, .. } => write!(f, "register {} does not exist", reg)?,
+            VmError::HeapFault { addr, op, .. } => write!(f, "segmentation fault at {:?}, address {}", op, addr)?,
+            VmError::StackUnderflow { .. } => write!(f, "stack underflow")?,
+            VmError::LimitExceeded { limit, .. } => write!(f, "{} limit exceeded", limit)?,
+            VmError::UnknownNative { name, .. } => write!(f, "no native function named {}", name)?,
+            VmError::Native { name, message, .. } => write!(f, "native {} failed: {}", name, message)?,
+            VmError::Io { message, .. } => write!(f, "io error: {}", message)?,
+            VmError::BadJump { target, .. } => write!(f, "bad jump target {}", target)?,
+            VmError::DivisionByZero { .. } => write!(f, "division by zero")?,
+            VmError::Overflow { .. } => write!(f, "arithmetic overflow")?,
+            VmError::BadScale { scale, .. } => write!(f, "bad index scale {}", scale)?,
+            VmError::BadExitCode { code, .. } => write!(f, "exit code {:#x} is outside 0..={}", code, MAX_HALT_CODE)?,
+            VmError::Format(err) => return write!(f, "invalid bytecode: {}", err),
+            VmError::Unverified(errors) => {
+                write!(f, "program failed verification")?;
+                for err in errors {
+                    write!(f, "\n  {}", err)?;
+                }
+                return Ok(());
+            }
+        }
+        write!(f, " (ip {})", self.ip())
+    }
+}
+
+impl std::error::Error for VmError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::rvasm;
+    use crate::engine::capture::run_captured;
+
+    #[test]
+    fn halt_exits_with_the_program_code() {
+        let output = run_captured(rvasm("HALT 64u2a\nMOV [1] 64u1\n").unwrap(), b"");
+        assert_eq!(output.status, Ok(ExitStatus::Halted(42)));
+        assert_eq!(output.status.unwrap().code(), 42);
+        //nothing after HALT runs
+        assert_eq!(output.registers[1], 0);
+        let output = run_captured(rvasm("MOV [1] 64u7f\nHALT [1]\n").unwrap(), b"");
+        assert_eq!(output.status, Ok(ExitStatus::Halted(MAX_HALT_CODE)));
+    }
+
+    #[test]
+    fn halt_codes_past_the_program_range_fault() {
+        let output = run_captured(rvasm("HALT 64u80\n").unwrap(), b"");
+        assert_eq!(output.status, Err(VmError::BadExitCode { ip: 0, code: 0x80 }));
+        let output = run_captured(rvasm("HALT 64iffffffffffffffff\n").unwrap(), b"");
+        let code = u64::MAX as u128;
+        assert_eq!(output.status, Err(VmError::BadExitCode { ip: 0, code }));
+    }
+
+    #[test]
+    fn fault_codes_are_distinct_and_clear_of_program_codes() {
+        let faults = [
+            VmError::InvalidOpcode { ip: 0, opcode: 0 },
+            VmError::MissingOperand { ip: 0 },
+            VmError::BadRegister { ip: 0, reg: 0 },
+            VmError::HeapFault { ip: 0, addr: 0, op: HeapOp::Read },
+            VmError::StackUnderflow { ip: 0 },
+            VmError::LimitExceeded { ip: 0, limit: Limit::Heap },
+            VmError::BadJump { ip: 0, target: 0 },
+            VmError::DivisionByZero { ip: 0 },
+            VmError::Overflow { ip: 0 },
+            VmError::BadScale { ip: 0, scale: 3 },
+            VmError::BadExitCode { ip: 0, code: 0 },
+            VmError::UnknownNative { ip: 0, name: String::new() },
+            VmError::Native { ip: 0, name: String::new(), message: String::new() },
+            VmError::Io { ip: 0, message: String::new() },
+            VmError::Unverified(Vec::new()),
+            VmError::Format(FormatError::BadMagic),
+        ];
+        let mut codes: Vec<i32> = faults.iter().map(VmError::exit_code).collect();
+        codes.push(ExitStatus::OutOfFuel.code());
+        for code in &codes {
+            assert!((VM_EXIT_CODES..=255).contains(code), "{} is outside the vm's range", code);
+        }
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), faults.len() + 1);
+    }
+}
@@ -0,0 +1,60 @@
+//fuel metering, every instruction burns fuel and the engine pauses when there is not enough left
+//for the next one, so untrusted programs can be stopped and resumed later
+use crate::bytecode::ops::Operations::{self, *};
+
+//fuel burnt by one instruction, anything that touches the heap, io or the callstack costs more
+pub fn cost(op: Operations) -> u64 {
+    match op {
+        ALLOC | REALLOC | FREE => 10,
+        WRITE | READ | READUNTIL | READLINE | FLUSH | NATIVE => 5,
+        CALL | RET => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::rvasm;
+    use crate::engine::capture::run_captured_with;
+    use crate::engine::{Engine, EngineConfig, ExitStatus};
+
+    const PROGRAM: &str = "MOV [1] 64u1\nALLOC [2] 64u8\nMOV [3] 64u1\n";
+
+    fn metered(fuel: u64) -> EngineConfig {
+        EngineConfig {
+            fuel: Some(fuel),
+            ..EngineConfig::default()
+        }
+    }
+
+    #[test]
+    fn running_out_stops_before_the_instruction_that_cannot_be_paid_for() {
+        //MOV costs 1 and ALLOC 10
+        let output = run_captured_with(rvasm(PROGRAM).unwrap(), b"", metered(5));
+        assert_eq!(output.status, Ok(ExitStatus::OutOfFuel));
+        assert_eq!(output.instructions, 1);
+        assert_eq!((output.registers[1], output.registers[2]), (1, 0));
+        let output = run_captured_with(rvasm(PROGRAM).unwrap(), b"", metered(12));
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+    }
+
+    #[test]
+    fn refuelling_resumes_where_it_stopped() {
+        let mut engine = Engine::with_config(metered(5));
+        assert_eq!(engine.run(rvasm(PROGRAM).unwrap()), Ok(ExitStatus::OutOfFuel));
+        assert_eq!((engine.fuel(), engine.fuel_consumed()), (Some(4), 1));
+        //still not enough, nothing runs
+        assert_eq!(engine.resume(), Ok(ExitStatus::OutOfFuel));
+        engine.refuel(10);
+        assert_eq!(engine.resume(), Ok(ExitStatus::Finished));
+        assert_eq!((engine.fuel(), engine.fuel_consumed()), (Some(3), 12));
+        assert_eq!(engine.regs.data[3], 1);
+    }
+
+    #[test]
+    fn engines_are_unmetered_by_default() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.run(rvasm(PROGRAM).unwrap()), Ok(ExitStatus::Finished));
+        assert_eq!((engine.fuel(), engine.fuel_consumed()), (None, 12));
+    }
+}
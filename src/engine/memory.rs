@@ -1,181 +1,373 @@
+use crate::engine::arith::NumType;
+
+//first fit free list allocator, the blocks cover the whole heap in address order and neighbouring
+//free blocks are always merged, so two free blocks are never next to each other
 pub struct Heap {
     memory: Vec<u8>,
-    allocated: Vec<(usize, usize)>,
-    allocated_size: usize,
+    blocks: Vec<Block>,
+    used: usize,
+    peak: usize,
+    allocations: usize,
+    frees: usize,
 }
 
 type Type = usize;
 
-impl Heap {
-    pub fn allocate(&mut self, size: usize) -> Result<Type, ()> {
-        //find a space in memory that is big enough to fit the data
-        //if there is no space, return an error
-        //otherwise, allocate the space and return the pointer
-        if let Some(start) = self.find_available_space(size) {
-            let end = start + size - 1;
-            self.allocated.push((start, end));
-            //fill the allocated space with 0s
-            for i in start..end {
-                self.memory[i] = 0;
-            }
-            Ok(start)
-        } else {
-            segfault(self.memory.len(), "Allocating OOB".to_string(), "allocate");
-            Err(())
-        }
+//blocks start on multiples of this and allocations are rounded up to it
+pub const ALIGN: usize = 8;
+
+//header of a block, kept outside the heap so programs cannot overwrite it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Block {
+    start: usize,
+    size: usize,
+    free: bool,
+}
+
+impl Block {
+    fn end(&self) -> usize {
+        self.start + self.size
     }
-    //write functions to allocate, realloc, free, read and write
-    //they should take into account allignment, padding, size etc
-    fn find_available_space(&self, size: usize) -> Option<usize> {
-        let mut start = 0;
+}
 
-        for &(allocated_start, allocated_end) in &self.allocated {
-            if allocated_start - start >= size {
-                return Some(start);
-            }
-            start = allocated_end + 1;
-        }
+//which heap operation faulted, reported back to the engine with the address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapOp {
+    Allocate,
+    Free,
+    Read,
+    Write,
+    Realloc,
+    Sizeof,
+}
 
-        if self.memory.len() - start >= size {
-            Some(start)
-        } else {
-            None
-        }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapFault {
+    pub addr: usize,
+    pub op: HeapOp,
+}
+
+//size rounded up to a multiple of ALIGN
+fn rounded(size: usize) -> usize {
+    size.div_ceil(ALIGN).saturating_mul(ALIGN)
+}
+
+fn fault<T>(addr: usize, op: HeapOp) -> Result<T, HeapFault> {
+    Err(HeapFault { addr, op })
+}
+
+//a snapshot of how the heap is being used, sizes are in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub size: usize,
+    //bytes in allocated blocks, including the padding from rounding up to ALIGN
+    pub used: usize,
+    //the most bytes that were allocated at once
+    pub peak: usize,
+    pub allocated_blocks: usize,
+    pub free_blocks: usize,
+    //the biggest allocation that can succeed right now
+    pub largest_free: usize,
+    //allocations and frees since the heap was made
+    pub allocations: usize,
+    pub frees: usize,
+}
+
+impl HeapStats {
+    pub fn free(&self) -> usize {
+        self.size - self.used
     }
-    pub fn free(&mut self, pos: usize) -> Result<(), ()> {
-        if let Some(start) = self
-            .allocated
-            .iter()
-            .position(|&(start, end)| pos >= start && pos <= end)
-        {
-            let range = self.allocated.remove(start);
-            for i in range.0..=range.1 {
-                self.memory[i] = 0;
-            }
-            Ok(())
-        } else {
-            segfault(pos, "out of bounds".to_string(), "free");
-            Err(())
+    //0 when all free memory is one block, towards 1 the more it is split into small pieces
+    pub fn fragmentation(&self) -> f64 {
+        match self.free() {
+            0 => 0.0,
+            free => 1.0 - self.largest_free as f64 / free as f64,
         }
     }
+}
+
+impl Heap {
     pub fn new(size: usize) -> Heap {
+        let blocks = match size {
+            0 => vec![],
+            _ => vec![Block { start: 0, size, free: true }],
+        };
         Heap {
             memory: vec![0; size],
-            allocated: vec![],
-            allocated_size: 0,
-        }
-    }
-    pub fn read(&self, pos: usize, size: usize) -> Result<Vec<u8>, ()> {
-        //read from pos to pos+size
-        let mut data = Vec::new();
-        for i in &self.memory[pos..pos + size] {
-            data.push(*i);
-        }
-        return Ok(data);
-    }
-    pub fn realloc(&mut self, pos: usize, size: usize) -> Result<usize, ()> {
-        //check if the extra space is available next to the allocated space, if it is, allocate it
-        //if it isn't, allocate a new space and copy the data over
-        //if the size is smaller than the allocated space, free the extra space
-        //if the size is 0, free the allocated space
-        //if the size is the same, do nothing
-        let (start, end) = self.allocated[pos];
-        //check if memory from end to end+size is available
-        if self.memory[end..end + size].iter().all(|&x| x == 0) {
-            //if it is, allocate it
-            self.allocated[pos] = (start, end + size);
-            //fill the allocated space with 0s
-            for i in start..end + size {
-                self.memory[i] = 0;
-            }
-            return Ok(start);
-        } else {
-            //otherwise, allocate a new space and copy the data over
-            let new_pos = self.allocate(size).unwrap();
-            for i in start..end {
-                self.memory[new_pos + i] = self.memory[i];
-            }
-            //free the old space
-            self.free(pos).unwrap();
-            return Ok(new_pos);
+            blocks,
+            used: 0,
+            peak: 0,
+            allocations: 0,
+            frees: 0,
+        }
+    }
+    pub fn allocate(&mut self, size: usize) -> Result<Type, HeapFault> {
+        if size == 0 {
+            return fault(0, HeapOp::Allocate);
         }
+        //the first free block big enough, taking the rounded up size unless the block is the
+        //unaligned end of the heap and only fits the exact size
+        let index = match self.blocks.iter().position(|block| block.free && block.size >= size) {
+            Some(index) => index,
+            None => return fault(self.memory.len(), HeapOp::Allocate),
+        };
+        let size = rounded(size).min(self.blocks[index].size);
+        self.blocks[index].free = false;
+        self.split(index, size);
+        let (start, end) = (self.blocks[index].start, self.blocks[index].end());
+        self.memory[start..end].fill(0);
+        self.used += size;
+        self.peak = self.peak.max(self.used);
+        self.allocations += 1;
+        Ok(start)
     }
-    pub fn sizeof(&self, pos: usize) -> Result<usize, ()> {
-        //return the size of the allocated space at pos
-        for (start, end) in self.allocated.iter() {
-            if pos >= *start && pos <= *end {
-                return Ok((end - start + 1) * 8);
+    //pos has to be the address allocate returned, freeing it twice faults
+    pub fn free(&mut self, pos: usize) -> Result<(), HeapFault> {
+        let index = match self.allocated_at(pos) {
+            Some(index) => index,
+            None => return fault(pos, HeapOp::Free),
+        };
+        self.used -= self.blocks[index].size;
+        self.frees += 1;
+        self.blocks[index].free = true;
+        self.coalesce(index);
+        Ok(())
+    }
+    //read from pos to pos+size, all of it has to be allocated just like for writes
+    pub fn read(&self, pos: usize, size: usize) -> Result<Vec<u8>, HeapFault> {
+        self.check_allocated(pos, size, HeapOp::Read)?;
+        Ok(self.memory[pos..pos + size].to_vec())
+    }
+    //resize the block allocated at pos, returning where its data now is. shrinking gives the tail
+    //back, growing takes the free block after it when that is big enough and moves the data to a
+    //new block otherwise. size 0 frees the block and returns None, since there is nowhere left
+    pub fn realloc(&mut self, pos: usize, size: usize) -> Result<Option<usize>, HeapFault> {
+        let index = match self.allocated_at(pos) {
+            Some(index) => index,
+            None => return fault(pos, HeapOp::Realloc),
+        };
+        if size == 0 {
+            self.free(pos)?;
+            return Ok(None);
+        }
+        let old = self.blocks[index];
+        let next = match self.blocks.get(index + 1) {
+            Some(next) if next.free => next.size,
+            _ => 0,
+        };
+        if old.size + next >= size {
+            let size = rounded(size).min(old.size + next);
+            if size > old.size {
+                self.blocks[index].size += self.blocks.remove(index + 1).size;
+                self.memory[old.end()..old.start + size].fill(0);
             }
+            self.split(index, size);
+            self.used = self.used + size - old.size;
+            self.peak = self.peak.max(self.used);
+            return Ok(Some(pos));
         }
-        segfault(pos, "out of bounds".to_string(), "sizeof");
-        return Err(());
+        let new_pos = self.allocate(size).map_err(|fault| HeapFault {
+            op: HeapOp::Realloc,
+            ..fault
+        })?;
+        self.memory.copy_within(old.start..old.end(), new_pos);
+        self.free(pos)?;
+        Ok(Some(new_pos))
     }
-    pub fn write(&mut self, pos: usize, data: u8) -> Result<(), ()> {
-        //if pos is within allocated memory, write to it
-        for (start, end) in self.allocated.iter() {
-            if pos >= *start && pos <= *end {
+    //bytes that can be used in the allocated block pos is in
+    pub fn sizeof(&self, pos: usize) -> Result<usize, HeapFault> {
+        match self.block_at(pos) {
+            Some(index) if !self.blocks[index].free => Ok(self.blocks[index].size),
+            _ => fault(pos, HeapOp::Sizeof),
+        }
+    }
+    //whether pos is an address allocate returned that has not been freed
+    pub fn allocated(&self, pos: usize) -> bool {
+        self.allocated_at(pos).is_some()
+    }
+    pub fn write(&mut self, pos: usize, data: u8) -> Result<(), HeapFault> {
+        //if pos is within allocated memory, write to it, otherwise segfault
+        match self.block_at(pos) {
+            Some(index) if !self.blocks[index].free => {
                 self.memory[pos] = data;
-                return Ok(());
+                Ok(())
+            }
+            _ => fault(pos, HeapOp::Write),
+        }
+    }
+    //write all of data from pos or none of it, faulting at the first address that is not allocated
+    pub fn write_all(&mut self, pos: usize, data: &[u8]) -> Result<(), HeapFault> {
+        self.check_allocated(pos, data.len(), HeapOp::Write)?;
+        self.memory[pos..pos + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+    pub fn stats(&self) -> HeapStats {
+        let free = self.blocks.iter().filter(|block| block.free);
+        HeapStats {
+            size: self.memory.len(),
+            used: self.used,
+            peak: self.peak,
+            allocated_blocks: self.blocks.iter().filter(|block| !block.free).count(),
+            free_blocks: free.clone().count(),
+            largest_free: free.map(|block| block.size).max().unwrap_or(0),
+            allocations: self.allocations,
+            frees: self.frees,
+        }
+    }
+    //index of the block containing pos
+    fn block_at(&self, pos: usize) -> Option<usize> {
+        let index = self.blocks.partition_point(|block| block.start <= pos).checked_sub(1)?;
+        match pos < self.blocks[index].end() {
+            true => Some(index),
+            false => None,
+        }
+    }
+    //fault at the first address from pos to pos+len that is not in an allocated block, walking
+    //whole blocks so a huge len from a program costs no more than the blocks it covers
+    fn check_allocated(&self, pos: usize, len: usize, op: HeapOp) -> Result<(), HeapFault> {
+        let end = pos.saturating_add(len);
+        let mut addr = pos;
+        while addr < end {
+            match self.block_at(addr) {
+                Some(index) if !self.blocks[index].free => addr = self.blocks[index].end(),
+                _ => return fault(addr, op),
             }
         }
-        //otherwise segfault
-        segfault(pos, "out of bounds".to_string(), "write");
-        return Err(());
+        Ok(())
+    }
+    //index of the allocated block starting at pos
+    fn allocated_at(&self, pos: usize) -> Option<usize> {
+        self.block_at(pos)
+            .filter(|&index| !self.blocks[index].free && self.blocks[index].start == pos)
+    }
+    //cut an allocated block down to size, the rest becomes a free block after it
+    fn split(&mut self, index: usize, size: usize) {
+        let block = self.blocks[index];
+        if block.size > size {
+            self.blocks[index].size = size;
+            let rest = Block {
+                start: block.start + size,
+                size: block.size - size,
+                free: true,
+            };
+            self.blocks.insert(index + 1, rest);
+            self.coalesce(index + 1);
+        }
+    }
+    //merge a free block with the free blocks either side of it
+    fn coalesce(&mut self, index: usize) {
+        if self.blocks.get(index + 1).is_some_and(|next| next.free) {
+            self.blocks[index].size += self.blocks.remove(index + 1).size;
+        }
+        if index > 0 && self.blocks[index - 1].free {
+            self.blocks[index - 1].size += self.blocks.remove(index).size;
+        }
     }
 }
 
-fn segfault(position: usize, reason: String, func: &str) {
-    //create a detailed message as to why the segfault occured and where, with color
-    let red = "\x1b[31m";
-    let reset = "\x1b[0m";
-    println!(
-        "{}Segmentation fault{} at {}, position: {}{}{}",
-        red, reset, func, red, position, reset
-    );
-    println!("Reason: {}", reason);
-    std::process::exit(0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackFault {
+    Overflow,
+    Underflow,
 }
 
+//number of slots in the default stack
+pub const STACK_SLOTS: usize = 1024;
+
+//every slot holds a whole register and the type of the value in it, so POP gives back exactly
+//what PUSH was given
 pub struct Stack {
-    memory: Vec<u8>,
+    memory: Vec<u128>,
+    types: Vec<Option<NumType>>,
     ptr: usize,
 }
 impl Stack {
     pub fn new() -> Stack {
+        Stack::with_slots(STACK_SLOTS)
+    }
+    pub fn with_slots(slots: usize) -> Stack {
         Stack {
-            memory: vec![0; 8192],
+            memory: vec![0; slots],
+            types: vec![None; slots],
             ptr: 0,
         }
     }
 }
 
 impl Stack {
-    pub fn push(&mut self, data: u8) {
-        self.memory[self.ptr] = data;
+    pub fn push(&mut self, data: u128) -> Result<(), StackFault> {
+        self.push_typed(data, None)
+    }
+    pub(crate) fn push_typed(&mut self, data: u128, tp: Option<NumType>) -> Result<(), StackFault> {
+        let slot = self.memory.get_mut(self.ptr).ok_or(StackFault::Overflow)?;
+        *slot = data;
+        self.types[self.ptr] = tp;
         self.ptr += 1;
+        Ok(())
     }
-    pub fn pop(&mut self) -> u8 {
+    pub fn pop(&mut self) -> Result<u128, StackFault> {
+        self.pop_typed().map(|(data, _)| data)
+    }
+    pub(crate) fn pop_typed(&mut self) -> Result<(u128, Option<NumType>), StackFault> {
+        if self.ptr == 0 {
+            return Err(StackFault::Underflow);
+        }
         self.ptr -= 1;
-        self.memory[self.ptr]
+        let tp = self.types[self.ptr].take();
+        Ok((std::mem::take(&mut self.memory[self.ptr]), tp))
+    }
+    pub fn peek(&self) -> Result<u128, StackFault> {
+        self.get(1)
     }
-    pub fn peek(&self) -> u8 {
-        self.memory[self.ptr - 1]
+    //the slot offset down from the top, 1 is the top
+    pub fn get(&self, offset: usize) -> Result<u128, StackFault> {
+        if offset == 0 || offset > self.ptr {
+            return Err(StackFault::Underflow);
+        }
+        Ok(self.memory[self.ptr - offset])
+    }
+    //the type of the value get would return, None for untyped values and missing slots
+    pub(crate) fn type_at(&self, offset: usize) -> Option<NumType> {
+        match offset == 0 || offset > self.ptr {
+            true => None,
+            false => self.types[self.ptr - offset],
+        }
     }
-    pub fn get(&self, offset: usize) -> u8 {
-        self.memory[self.ptr - offset]
+    pub fn swap(&mut self) -> Result<(), StackFault> {
+        if self.ptr < 2 {
+            return Err(StackFault::Underflow);
+        }
+        self.memory.swap(self.ptr - 1, self.ptr - 2);
+        self.types.swap(self.ptr - 1, self.ptr - 2);
+        Ok(())
     }
-    pub fn swap(&mut self) {
-        let temp = self.memory[self.ptr - 1];
-        self.memory[self.ptr - 1] = self.memory[self.ptr - 2];
-        self.memory[self.ptr - 2] = temp;
+    pub fn dup(&mut self) -> Result<(), StackFault> {
+        let top = self.peek()?;
+        self.push_typed(top, self.type_at(1))
     }
-    pub fn dup(&mut self) {
-        self.memory[self.ptr] = self.memory[self.ptr - 1];
-        self.ptr += 1;
+    //number of slots in use
+    pub fn pointer(&self) -> usize {
+        self.ptr
     }
-    pub fn drop(&mut self) {
-        self.memory[self.ptr] = 0;
-        self.ptr -= 1;
+    //number of slots the stack can hold
+    pub fn capacity(&self) -> usize {
+        self.memory.len()
+    }
+    //drop everything above base, used to throw away a call frame
+    //a function that popped its arguments leaves the stack below base, which is left alone
+    pub fn unwind(&mut self, base: usize) {
+        if base < self.ptr {
+            self.memory[base..self.ptr].fill(0);
+            self.types[base..self.ptr].fill(None);
+            self.ptr = base;
+        }
+    }
+    //everything currently on the stack, bottom first
+    pub fn contents(&self) -> &[u128] {
+        &self.memory[..self.ptr]
+    }
+    pub fn discard(&mut self) -> Result<(), StackFault> {
+        self.pop().map(|_| ())
     }
 }
 
@@ -189,3 +381,202 @@ impl Default for Heap {
         Self::new(8192)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //(start, size, free) for every block, in address order
+    fn layout(heap: &Heap) -> Vec<(usize, usize, bool)> {
+        heap.blocks.iter().map(|block| (block.start, block.size, block.free)).collect()
+    }
+
+    #[test]
+    fn allocating_splits_the_free_block() {
+        let mut heap = Heap::new(64);
+        assert_eq!(heap.allocate(5), Ok(0));
+        assert_eq!(layout(&heap), [(0, 8, false), (8, 56, true)]);
+        assert_eq!(heap.allocate(16), Ok(8));
+        assert_eq!(layout(&heap), [(0, 8, false), (8, 16, false), (24, 40, true)]);
+        //a block that fits exactly is not split
+        assert_eq!(heap.allocate(40), Ok(24));
+        assert_eq!(heap.stats().free_blocks, 0);
+        assert_eq!(heap.allocate(1), Err(HeapFault { addr: 64, op: HeapOp::Allocate }));
+    }
+
+    #[test]
+    fn the_unaligned_end_of_the_heap_fits_its_exact_size() {
+        let mut heap = Heap::new(20);
+        assert_eq!(heap.allocate(16), Ok(0));
+        assert_eq!(heap.allocate(4), Ok(16));
+        assert_eq!(heap.sizeof(16), Ok(4));
+    }
+
+    #[test]
+    fn freeing_merges_with_the_next_block() {
+        let mut heap = Heap::new(64);
+        let [a, b, c] = [8; 3].map(|size| heap.allocate(size).unwrap());
+        heap.free(c).unwrap();
+        assert_eq!(layout(&heap), [(a, 8, false), (b, 8, false), (c, 48, true)]);
+        heap.free(b).unwrap();
+        assert_eq!(layout(&heap), [(a, 8, false), (b, 56, true)]);
+    }
+
+    #[test]
+    fn freeing_merges_with_the_previous_block() {
+        let mut heap = Heap::new(64);
+        let [a, b, c] = [8; 3].map(|size| heap.allocate(size).unwrap());
+        heap.free(a).unwrap();
+        heap.free(b).unwrap();
+        assert_eq!(layout(&heap), [(a, 16, true), (c, 8, false), (24, 40, true)]);
+    }
+
+    #[test]
+    fn freeing_merges_with_both_neighbours() {
+        let mut heap = Heap::new(64);
+        let [a, b, c, d] = [8; 4].map(|size| heap.allocate(size).unwrap());
+        heap.free(a).unwrap();
+        heap.free(c).unwrap();
+        assert_eq!(
+            layout(&heap),
+            [(a, 8, true), (b, 8, false), (c, 8, true), (d, 8, false), (32, 32, true)]
+        );
+        heap.free(b).unwrap();
+        assert_eq!(layout(&heap), [(a, 24, true), (d, 8, false), (32, 32, true)]);
+        heap.free(d).unwrap();
+        assert_eq!(layout(&heap), [(0, 64, true)]);
+    }
+
+    #[test]
+    fn bad_frees_fault_and_change_nothing() {
+        let mut heap = Heap::new(64);
+        let a = heap.allocate(16).unwrap();
+        let before = layout(&heap);
+        //an address inside the block rather than its start
+        assert_eq!(heap.free(a + 4), Err(HeapFault { addr: a + 4, op: HeapOp::Free }));
+        //free memory and addresses past the heap
+        assert_eq!(heap.free(32), Err(HeapFault { addr: 32, op: HeapOp::Free }));
+        assert_eq!(heap.free(64), Err(HeapFault { addr: 64, op: HeapOp::Free }));
+        assert_eq!(layout(&heap), before);
+        heap.free(a).unwrap();
+        assert_eq!(heap.free(a), Err(HeapFault { addr: a, op: HeapOp::Free }));
+        assert_eq!(heap.stats().frees, 1);
+    }
+
+    #[test]
+    fn stats_track_usage_and_fragmentation() {
+        let mut heap = Heap::new(64);
+        let empty = heap.stats();
+        assert_eq!((empty.free(), empty.largest_free, empty.fragmentation()), (64, 64, 0.0));
+        let _a = heap.allocate(8).unwrap();
+        let b = heap.allocate(3).unwrap();
+        let _c = heap.allocate(8).unwrap();
+        heap.free(b).unwrap();
+        let stats = heap.stats();
+        assert_eq!(
+            stats,
+            HeapStats {
+                size: 64,
+                //rounded up to ALIGN
+                used: 16,
+                peak: 24,
+                allocated_blocks: 2,
+                free_blocks: 2,
+                largest_free: 40,
+                allocations: 3,
+                frees: 1,
+            }
+        );
+        assert_eq!(stats.free(), 48);
+        assert_eq!(stats.fragmentation(), 1.0 - 40.0 / 48.0);
+        //a full heap has nothing to fragment
+        heap.allocate(40).unwrap();
+        heap.allocate(8).unwrap();
+        assert_eq!(heap.stats().free(), 0);
+        assert_eq!(heap.stats().fragmentation(), 0.0);
+    }
+
+    #[test]
+    fn reads_fault_on_memory_that_is_not_allocated() {
+        let mut heap = Heap::new(64);
+        let [a, b] = [8; 2].map(|size| heap.allocate(size).unwrap());
+        heap.write_all(a, &[1; 8]).unwrap();
+        //a read can cross from one allocated block into the next
+        assert_eq!(heap.read(a, 16), Ok([[1; 8], [0; 8]].concat()));
+        heap.free(a).unwrap();
+        assert_eq!(heap.read(a, 1), Err(HeapFault { addr: a, op: HeapOp::Read }));
+        //never allocated, and running off the end of a block
+        assert_eq!(heap.read(32, 1), Err(HeapFault { addr: 32, op: HeapOp::Read }));
+        assert_eq!(heap.read(b, 9), Err(HeapFault { addr: 16, op: HeapOp::Read }));
+        assert_eq!(heap.read(b, usize::MAX), Err(HeapFault { addr: 16, op: HeapOp::Read }));
+        assert_eq!(heap.read(b, 8), Ok(vec![0; 8]));
+    }
+
+    #[test]
+    fn realloc_shrinks_and_gives_the_tail_back() {
+        let mut heap = Heap::new(64);
+        let a = heap.allocate(32).unwrap();
+        heap.write_all(a, &[7; 32]).unwrap();
+        assert_eq!(heap.realloc(a, 5), Ok(Some(a)));
+        assert_eq!(layout(&heap), [(a, 8, false), (8, 56, true)]);
+        assert_eq!(heap.read(a, 8), Ok(vec![7; 8]));
+        assert_eq!(heap.stats().used, 8);
+    }
+
+    #[test]
+    fn realloc_grows_in_place_into_a_free_neighbour() {
+        let mut heap = Heap::new(64);
+        let [a, b] = [8; 2].map(|size| heap.allocate(size).unwrap());
+        heap.write_all(a, &[1; 8]).unwrap();
+        heap.write_all(b, &[2; 8]).unwrap();
+        heap.free(b).unwrap();
+        assert_eq!(heap.realloc(a, 20), Ok(Some(a)));
+        assert_eq!(layout(&heap), [(a, 24, false), (24, 40, true)]);
+        //the old data stays and the new part starts zeroed
+        assert_eq!(heap.read(a, 16), Ok([[1; 8], [0; 8]].concat()));
+        assert_eq!(heap.stats().used, 24);
+    }
+
+    #[test]
+    fn realloc_moves_and_copies_when_it_cannot_grow_in_place() {
+        let mut heap = Heap::new(64);
+        let [a, b] = [8; 2].map(|size| heap.allocate(size).unwrap());
+        let data: Vec<u8> = (1..=8).collect();
+        heap.write_all(a, &data).unwrap();
+        let moved = heap.realloc(a, 16).unwrap().unwrap();
+        assert_eq!(moved, 16);
+        assert_eq!(heap.read(moved, 8), Ok(data));
+        assert!(!heap.allocated(a));
+        assert_eq!(
+            layout(&heap),
+            [(a, 8, true), (b, 8, false), (moved, 16, false), (32, 32, true)]
+        );
+    }
+
+    #[test]
+    fn realloc_to_zero_frees_the_block() {
+        let mut heap = Heap::new(64);
+        let a = heap.allocate(8).unwrap();
+        assert_eq!(heap.realloc(a, 0), Ok(None));
+        assert!(!heap.allocated(a));
+        assert_eq!(layout(&heap), [(0, 64, true)]);
+        assert_eq!(heap.stats().frees, 1);
+    }
+
+    #[test]
+    fn realloc_faults_on_addresses_allocate_did_not_return() {
+        let mut heap = Heap::new(64);
+        let a = heap.allocate(16).unwrap();
+        for pos in [a + 4, 32, 64] {
+            assert_eq!(heap.realloc(pos, 8), Err(HeapFault { addr: pos, op: HeapOp::Realloc }));
+        }
+        heap.free(a).unwrap();
+        assert_eq!(heap.realloc(a, 8), Err(HeapFault { addr: a, op: HeapOp::Realloc }));
+        //running out of room faults too, and leaves the block where it was
+        let a = heap.allocate(16).unwrap();
+        let _b = heap.allocate(48).unwrap();
+        assert_eq!(heap.realloc(a, 32), Err(HeapFault { addr: 64, op: HeapOp::Realloc }));
+        assert!(heap.allocated(a));
+        assert_eq!(heap.sizeof(a), Ok(16));
+    }
+}
@@ -1,24 +1,41 @@
 #![allow(non_camel_case_types)]
+mod arith;
 mod callstack;
+pub mod capture;
+pub mod config;
+mod debugger;
+mod fuel;
+pub mod native;
+pub mod error;
 pub mod memory;
 mod regs;
-mod stdio;
+pub mod stdio;
 
 use crate::{
     builder::bytes::*,
     bytecode::{
         data::ByteData,
+        format,
         ops::ArgType::*,
         ops::Operations::{self, *},
         ops::*,
-        types::Types,
+        types::{Indexed, Types},
     },
     engine::memory::Heap,
+    verify,
 };
-use callstack::FnCall;
-use stdio::IO;
+use arith::{ArithError, ArithOp, NumType};
+use callstack::Frame;
+pub use arith::OverflowMode;
+pub use config::{EngineConfig, Limit};
+pub use error::{ExitStatus, StepResult, VmError, MAX_HALT_CODE, VM_EXIT_CODES};
+pub use native::EngineCtx;
+pub use stdio::{Io, MemoryIo, StdinLines, StreamIo};
+pub use regs::{ARG_REGS, REGISTER_COUNT, SAVED_REGS};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 
-type Register = u64;
+type Register = u128;
 type Address = u64;
 
 type type_t = u8;
@@ -26,65 +43,144 @@ type size_t = usize;
 type reg_t = usize;
 
 pub struct Engine {
-    accumulator: u64,
+    accumulator: Register,
+    //the type of the value in the accumulator, registers and stack slots keep theirs too
+    accumulator_type: Option<NumType>,
     pub regs: regs::Registers,
     callstack: callstack::CallStack,
     pub heap: memory::Heap,
     stack: memory::Stack,
-    io: stdio::IO,
+    io: Box<dyn Io>,
     debug: bool,
+    //run the verifier over programs before executing them
+    verify: bool,
+    //whether integer arithmetic wraps or faults when it overflows
+    overflow: OverflowMode,
     ip: usize,
+    //ip of the op currently being executed, used when reporting errors
+    op_ip: usize,
     data: ByteStream,
-    jumptable: Vec<usize>,
+    //function name (as packed by stringtohex) to the start of its body
+    functions: HashMap<u64, usize>,
+    //body start to the number of arguments the function declares
+    arity: HashMap<usize, usize>,
+    //host functions by packed name
+    natives: HashMap<u64, native::Native>,
+    //instructions executed and bytes written since the program was loaded, checked against config
+    executed: u64,
+    written: usize,
+    //fuel left, None when unmetered, and fuel burnt since the program was loaded
+    fuel: Option<u64>,
+    consumed: u64,
+    //exit code given to HALT, the program is over once this is set
+    halted: Option<i32>,
+    config: EngineConfig,
 }
 
+//engines are moved between threads by hosts that run many programs, so keep everything they own Send
+const _: fn() = || {
+    fn is_send<T: Send>() {}
+    is_send::<Engine>();
+};
+
 /*
 ====================
     VM Functions
 ====================
 */
 impl Engine {
-    pub fn alloc(&mut self, size: size_t, reg: reg_t) -> Address {
-        let addr = self.heap.allocate(size).unwrap() as u64;
-        self.move_reg(reg, addr);
-        addr
+    pub fn alloc(&mut self, size: size_t, reg: reg_t) -> Result<Address, VmError> {
+        let ip = self.op_ip;
+        //allocating 0 bytes is a fault, anything else that fails ran out of heap
+        let addr = self.heap.allocate(size).map_err(|fault| match size {
+            0 => VmError::heap(ip, fault),
+            _ => VmError::LimitExceeded { ip, limit: Limit::Heap },
+        })? as u64;
+        self.move_reg(reg, addr as Register);
+        Ok(addr)
     }
-    fn free(&mut self, addr: Address) {
-        self.heap.free(addr as usize).unwrap();
+    fn free(&mut self, addr: Address) -> Result<(), VmError> {
+        let ip = self.op_ip;
+        self.heap
+            .free(addr as usize)
+            .map_err(|fault| VmError::heap(ip, fault))
     }
-    pub fn move_reg(&mut self, reg: reg_t, value: u64) {
-        self.regs[reg] = value; // optimized
+    //the value is untyped, like anything read from the heap
+    pub fn move_reg(&mut self, reg: reg_t, value: Register) {
+        self.regs.set(reg, value, None);
     }
     pub fn new() -> Self {
-        Self {
-            accumulator: 0,
-            regs: regs::Registers::default(),
-            callstack: callstack::CallStack::default(),
-            heap: memory::Heap::default(),
-            stack: memory::Stack::default(),
-            io: stdio::IO::default(),
-            debug: false,
-            ip: 0,
-            data: ByteStream::new(),
-            jumptable: Vec::new(),
-        }
+        Self::with_config(EngineConfig::default())
     }
     pub fn new_with_size(heap_size: size_t) -> Self {
+        Self::with_config(EngineConfig {
+            heap_size,
+            ..EngineConfig::default()
+        })
+    }
+    pub fn with_config(config: EngineConfig) -> Self {
         Self {
             accumulator: 0,
+            accumulator_type: None,
             regs: regs::Registers::default(),
-            callstack: callstack::CallStack::default(),
-            heap: memory::Heap::new(heap_size),
-            stack: memory::Stack::default(),
-            io: stdio::IO::default(),
+            callstack: callstack::CallStack::new(config.max_call_depth),
+            heap: memory::Heap::new(config.heap_size),
+            stack: memory::Stack::with_slots(config.stack_slots),
+            io: Box::new(StreamIo::stdio()),
             debug: false,
+            verify: false,
+            overflow: OverflowMode::Wrap,
             ip: 0,
+            op_ip: 0,
             data: ByteStream::new(),
-            jumptable: Vec::new(),
+            functions: HashMap::new(),
+            arity: HashMap::new(),
+            natives: HashMap::new(),
+            executed: 0,
+            written: 0,
+            fuel: config.fuel,
+            consumed: 0,
+            halted: None,
+            config,
         }
     }
-    fn realloc(&mut self, addr: Address, size: size_t) -> Address {
-        self.heap.realloc(addr as usize, size).unwrap() as u64
+    //make a rust function callable from bytecode with NATIVE :name, names are 1 to 8 ascii
+    //characters like function names, false if the name cannot be used
+    pub fn register_native<F>(&mut self, name: &str, native: F) -> bool
+    where
+        F: FnMut(&mut EngineCtx) -> Result<(), VmError> + Send + 'static,
+    {
+        match format::pack_name(name) {
+            Some(packed) => {
+                self.natives.insert(packed, Box::new(native));
+                true
+            }
+            None => false,
+        }
+    }
+    //where READ reads from and WRITE writes to, stdin and stdout unless this is called
+    pub fn set_io(&mut self, io: Box<dyn Io>) {
+        self.io = io;
+    }
+    //check programs with the verifier before running them
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+    //choose between wrapping and faulting on integer overflow
+    pub fn set_overflow(&mut self, mode: OverflowMode) {
+        self.overflow = mode;
+    }
+    fn realloc(&mut self, addr: Address, size: size_t) -> Result<Option<Address>, VmError> {
+        let ip = self.op_ip;
+        let valid = self.heap.allocated(addr as usize);
+        //resizing a real block only fails when there is no room to move it to
+        self.heap
+            .realloc(addr as usize, size)
+            .map(|addr| addr.map(|addr| addr as u64))
+            .map_err(|fault| match valid {
+                true => VmError::LimitExceeded { ip, limit: Limit::Heap },
+                false => VmError::heap(ip, fault),
+            })
     }
 }
 
@@ -100,298 +196,477 @@ impl Default for Engine {
 ====================
 */
 impl Engine {
-    pub fn run(&mut self, bytes: ByteStream) {
-        self.data = bytes.clone();
-        //find all TypeFuncs and store them in a jumptable
-        for i in 0..bytes.bytes.len() {
-            let byte = bytes.bytes[i].clone();
-            match byte.tp {
-                Types::TypeFunc => {
-                    self.jumptable.push(i)
-                },
-                _ => (),
+    pub fn run(&mut self, bytes: ByteStream) -> Result<ExitStatus, VmError> {
+        self.load(bytes)?;
+        self.resume()
+    }
+    //carry on from where the program stopped, after running out of fuel for example
+    pub fn resume(&mut self) -> Result<ExitStatus, VmError> {
+        //iterate through the bytes and pass them to a handler
+        loop {
+            match self.step() {
+                StepResult::Ran => (),
+                StepResult::Exited(status) => return Ok(status),
+                StepResult::Fault(err) => return Err(err),
             }
         }
-        //iterate through the bytes and pass them to a handler
-        while self.ip < bytes.bytes.len() {
-            let byte = bytes.bytes[self.ip].clone();
-            self.handle(byte);
+    }
+    //execute at most n instructions, returning Paused if the program is not done by then
+    pub fn run_for(&mut self, n: u64) -> Result<ExitStatus, VmError> {
+        if self.finished() {
+            return Ok(self.exit_status());
+        }
+        for _ in 0..n {
+            match self.step() {
+                StepResult::Ran => (),
+                StepResult::Exited(status) => return Ok(status),
+                StepResult::Fault(err) => return Err(err),
+            }
         }
+        Ok(ExitStatus::Paused)
+    }
+    //execute the single instruction at ip
+    pub fn step(&mut self) -> StepResult {
+        if self.finished() {
+            return StepResult::Exited(self.exit_status());
+        }
+        if !self.burn() {
+            return StepResult::Exited(ExitStatus::OutOfFuel);
+        }
+        //output still buffered is flushed once the program is over
+        match self.execute() {
+            Err(err) => {
+                let _ = self.io.flush();
+                StepResult::Fault(err)
+            }
+            Ok(()) if self.finished() => match self.io.flush() {
+                Ok(()) => StepResult::Exited(self.exit_status()),
+                Err(err) => StepResult::Fault(VmError::io(self.op_ip, err)),
+            },
+            Ok(()) => StepResult::Ran,
+        }
+    }
+    //add fuel, metering starts if the engine was unmetered
+    pub fn refuel(&mut self, amount: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(amount));
+    }
+    //fuel left, None when unmetered
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
     }
-    pub fn debug(&mut self, bytes: ByteStream) {
+    pub fn fuel_consumed(&self) -> u64 {
+        self.consumed
+    }
+    //take the fuel for the instruction at ip, false if there is not enough
+    fn burn(&mut self) -> bool {
+        //invalid ops cost 1 and fault when they are executed
+        let cost = self.decode_op(&self.data.bytes[self.ip]).map_or(1, fuel::cost);
+        match self.fuel {
+            Some(fuel) if fuel < cost => return false,
+            Some(fuel) => self.fuel = Some(fuel - cost),
+            None => (),
+        }
+        self.consumed += cost;
+        true
+    }
+    pub fn debug(&mut self, bytes: ByteStream) -> Result<ExitStatus, VmError> {
         self.debug = true;
-        self.run(bytes);
+        self.load(bytes)?;
+        let mut debugger = debugger::Debugger::new();
+        debugger.repl(self, stdio::StdinLines::default(), std::io::stdout())
+    }
+    //get ready to run a program from its entry point, checking it first if verifying is on
+    //nothing carries over from an earlier program: registers, the accumulator, stack, heap and
+    //call frames start empty and fuel goes back to what the config gives
+    pub fn load(&mut self, bytes: ByteStream) -> Result<(), VmError> {
+        if self.verify {
+            verify::verify(&bytes).map_err(VmError::Unverified)?;
+        }
+        self.data = bytes;
+        self.ip = self.data.entry;
+        self.functions.clear();
+        self.arity.clear();
+        self.accumulator = 0;
+        self.accumulator_type = None;
+        self.regs = regs::Registers::default();
+        self.stack = memory::Stack::with_slots(self.config.stack_slots);
+        self.heap = memory::Heap::new(self.config.heap_size);
+        self.callstack.clear();
+        self.executed = 0;
+        self.written = 0;
+        self.fuel = self.config.fuel;
+        self.consumed = 0;
+        self.halted = None;
+        //find all FUNC labels and store where their bodies start
+        for symbol in format::symbols(&self.data) {
+            let name = self.data.bytes[symbol.index + 1].unwrap();
+            self.functions.insert(name, symbol.body());
+            if let Some(args) = symbol.args {
+                self.arity.insert(symbol.body(), args);
+            }
+        }
+        Ok(())
+    }
+    pub fn finished(&self) -> bool {
+        self.halted.is_some() || self.ip >= self.data.bytes.len()
+    }
+    //how a finished program ended
+    fn exit_status(&self) -> ExitStatus {
+        match self.halted {
+            Some(code) => ExitStatus::Halted(code),
+            None => ExitStatus::Finished,
+        }
+    }
+    //index of the next instruction
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+    pub fn accumulator(&self) -> u128 {
+        self.accumulator
+    }
+    //the value stack, bottom first
+    pub fn stack(&self) -> &[u128] {
+        self.stack.contents()
     }
-    fn handle(&mut self, byte: Byte) {
-        let op: Operations = Operations::from(byte);
+    //instructions executed since the program was loaded
+    pub fn instructions(&self) -> u64 {
+        self.executed
+    }
+    //number of calls that have not returned yet
+    pub fn call_depth(&self) -> usize {
+        self.callstack.len()
+    }
+    fn execute(&mut self) -> Result<(), VmError> {
+        if self.config.max_instructions.is_some_and(|max| self.executed >= max) {
+            return Err(VmError::LimitExceeded {
+                ip: self.ip,
+                limit: Limit::Instructions,
+            });
+        }
+        self.executed += 1;
+        let byte = self.data.bytes[self.ip].clone();
+        self.handle(byte)
+    }
+    fn handle(&mut self, byte: Byte) -> Result<(), VmError> {
+        self.op_ip = self.ip;
+        let op = self.decode_op(&byte)?;
         self.ip += 1;
         match op {
-            NOP => {
-                ()
-            }
+            NOP => {}
             ADD => {
-                let mut args = self.get_args(&MATH_OP_ARGS);
-                let left = args[0] as u64;
-                let right = args[1] as u64;
-                self.accumulator =  left + right
+                self.arith(ArithOp::Add)?;
             }
             SUB => {
-                let mut args = self.get_args(&MATH_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = (left - right) as u64;
+                self.arith(ArithOp::Sub)?;
             }
             WRITE => {
-                let args = self.get_args(&IO_OUT_OP_ARGS);
+                let args = self.get_args(&IO_OUT_OP_ARGS)?;
                 let reg = args[0];
                 let size = args[1];
-                let to_write = self.heap.read(reg as usize, size).unwrap();
-                self.io.write(&to_write);
+                if self.config.max_output.is_some_and(|max| self.written.saturating_add(size) > max) {
+                    return Err(VmError::LimitExceeded {
+                        ip: self.op_ip,
+                        limit: Limit::Output,
+                    });
+                }
+                let to_write = self.heap_read(reg, size)?;
+                let ip = self.op_ip;
+                self.io.write(&to_write).map_err(|err| VmError::io(ip, err))?;
+                self.written += size;
             }
             FLUSH => {
-                self.io.flush();
+                let ip = self.op_ip;
+                self.io.flush().map_err(|err| VmError::io(ip, err))?;
             }
             INC => {
                 //takes 2 args, addr and value, if type of addr is TypeReg, increment the value of the register
                 //if it's not, increment the value at the address in heap
                 //get first byte
-                let byte = self.read_byte();
-                let val = self.read_byte();
-                let val = self.handle_typed(val);
+                let byte = self.read_byte()?;
+                let val = self.read_byte()?;
+                let val = self.handle_typed(val)?;
                 match byte.tp {
                     Types::TypeReg => {
-                        self.regs[byte.unwrap() as usize] += val as u64;
+                        let reg = self.check_reg(byte.unwrap() as usize)?;
+                        self.regs[reg] = self.regs[reg].wrapping_add(val as Register);
                     }
                     _ => {
-                        let value = self.heap.read(byte.unwrap() as usize, 1).unwrap()[0];
-                        let _ = self.heap.write(byte.unwrap() as usize, value + 1);
+                        let addr = byte.unwrap() as usize;
+                        //the byte wraps, so only the low 8 bits of the amount matter
+                        let value = self.heap_read(addr, 1)?[0];
+                        self.heap_write(addr, value.wrapping_add(val as u8))?;
                     }
                 }
             }
             DEC => {
-                let args = self.get_args(&REG_OP_ARGS);
+                let args = self.get_args(&DEC_OP_ARGS)?;
                 let reg = args[0];
-                self.regs[reg] -= 1;
+                self.regs[reg] = self.regs[reg].wrapping_sub(1);
             }
             STORE => {
-                let args = self.get_args(&STORE_OP_ARGS);
+                let args = self.get_args(&STORE_OP_ARGS)?;
                 let addr = args[0];
                 let len = args[1];
                 //read as many args as the length
                 for i in 0..len {
-                    let bt = self.read_byte();
-                    let byte = self.handle_typed(bt);
-                    let res = self.heap.write(addr as usize + i, byte as u8);
+                    let bt = self.read_byte()?;
+                    let byte = self.handle_typed(bt)?;
+                    self.heap_write(addr.wrapping_add(i), byte as u8)?;
                 }
             }
+            LOAD8 | LOAD16 | LOAD32 | LOAD64 => {
+                let args = self.get_args(&LOAD_WIDTH_OP_ARGS)?;
+                //the offset wraps, so a negative offset reaches below the base
+                let addr = args[1].wrapping_add(args[2]);
+                let data = self.heap_read(addr, op.width().unwrap_or(1))?;
+                let mut value = [0; 16];
+                value[..data.len()].copy_from_slice(&data);
+                self.move_reg(args[0], Register::from_le_bytes(value));
+            }
+            STORE8 | STORE16 | STORE32 | STORE64 => {
+                let args = self.get_args(&STORE_WIDTH_OP_ARGS[..2])?;
+                let addr = args[0].wrapping_add(args[1]);
+                let value = self.read_byte()?;
+                let value = self.handle_value(value)?.to_le_bytes();
+                let ip = self.op_ip;
+                self.heap
+                    .write_all(addr, &value[..op.width().unwrap_or(1)])
+                    .map_err(|fault| VmError::heap(ip, fault))?;
+            }
             LOAD => {
-                let args = self.get_args(&LOAD_OP_ARGS);
+                let args = self.get_args(&LOAD_OP_ARGS)?;
                 let addr = args[0];
                 let location = args[1];
-                let data = self.heap.read(location as usize, 1).unwrap();
-                self.move_reg(addr, data[0] as u64);
+                let data = self.heap_read(location, 1)?;
+                self.move_reg(addr, data[0] as Register);
+            }
+            NATIVE => {
+                let name = self.read_byte()?.unwrap();
+                self.call_native(name)?;
             }
             FUNC => {
-                let args = self.get_args(&[Typed]);
-                let jp = args[0];
-                self.jumptable.push(jp as usize);
+                //labels are collected when the program is loaded, so just skip the name and
+                //argument count
+                self.read_byte()?;
+                if self.data.bytes.get(self.ip).is_some_and(|byte| byte.tp.is_literal()) {
+                    self.ip += 1;
+                }
             }
             ALLOC => {
-                let mut args = self.get_args(&ALLOC_ARGS);
+                let mut args = self.get_args(&ALLOC_ARGS)?;
                 let reg = args[0];
                 let size = args[1];
-                let ptr = self.alloc(size, reg);
-                self.move_reg(reg, ptr);
+                self.alloc(size, reg)?;
             }
             FREE => {
-                let args = self.get_args(&FREE_ARGS);
+                let args = self.get_args(&FREE_ARGS)?;
                 let addr = args[0];
-                self.free(addr as u64);
+                self.free(addr as u64)?;
             }
             JMP => {
-                let args = self.get_args(&JMP_ARGS);
+                let args = self.get_args(&JMP_ARGS)?;
                 let addr = args[0];
-                self.ip = addr;
+                self.jump(addr)?;
+            }
+            HALT => {
+                let code = self.read_byte()?;
+                let code = self.handle_value(code)?;
+                if code > MAX_HALT_CODE as u128 {
+                    return Err(VmError::BadExitCode { ip: self.op_ip, code });
+                }
+                self.halted = Some(code as i32);
             }
             CALL => {
-                let args = self.get_args(&CALL_OP_ARGS);
+                let args = self.get_args(&CALL_OP_ARGS)?;
                 let addr = args[0];
-                self.callstack.push(FnCall { ret: self.ip as u8 });
-                self.ip = addr;
+                self.call(addr)?;
             }
             RET => {
-                let ret = self.callstack.pop().unwrap();
-                self.ip = ret.ret as usize;
+                self.ret()?;
             }
             MOV => {
-                let args = self.get_args(&REG_OP_ARGS);
-                let addr = args[0];
-                let value = args[1];
-                self.move_reg(addr, value as u64);
+                let reg = self.get_args(&REG_OP_ARGS[..1])?[0];
+                let value = self.read_byte()?;
+                let tp = self.operand_type(&value);
+                let value = self.handle_value(value)?;
+                self.regs.set(reg, value, tp);
             }
             WRACC => {
-                let args = self.get_args(&WRACC_ARGS);
-                self.accumulator = args[0] as u64;
+                let value = self.read_byte()?;
+                let tp = self.operand_type(&value);
+                let value = self.handle_value(value)?;
+                self.set_accumulator(value, tp);
             }
             REACC => {
-                let args = self.get_args(&REACC_ARGS);
+                let args = self.get_args(&REACC_ARGS)?;
                 let reg = args[0];
-                self.move_reg(reg, self.accumulator);
+                self.regs.set(reg, self.accumulator, self.accumulator_type);
             }
             PUSH => {
-                let args = self.get_args(&PUSH_OP_ARGS);
-                let reg = args[0];
-                let value = self.regs[reg];
-                self.stack.push(value as u8);
+                let value = self.read_byte()?;
+                let tp = self.operand_type(&value);
+                let value = self.handle_value(value)?;
+                let ip = self.op_ip;
+                self.stack.push_typed(value, tp).map_err(|fault| VmError::stack(ip, fault))?;
             }
             POP => {
-                let args = self.get_args(&REG_OP_ARGS);
+                let args = self.get_args(&POP_OP_ARGS)?;
                 let reg = args[0];
-                let value = self.stack.pop() as u64;
-                self.move_reg(reg, value);
+                let ip = self.op_ip;
+                let (value, tp) = self
+                    .stack
+                    .pop_typed()
+                    .map_err(|fault| VmError::stack(ip, fault))?;
+                self.regs.set(reg, value, tp);
             }
             MUL => {
-                let args = self.get_args(&MATH_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = left as u64 * right as u64;
+                self.arith(ArithOp::Mul)?;
             }
             DIV => {
-                let args = self.get_args(&MATH_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = left as u64 / right as u64;
+                self.arith(ArithOp::Div)?;
             }
             MOD => {
-                let args = self.get_args(&MATH_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = left as u64 % right as u64;
+                self.arith(ArithOp::Mod)?;
             }
             REALLOC => {
-                let args = self.get_args(&REALLOC_ARGS);
-                let addr = args[0];
+                let args = self.get_args(&REALLOC_ARGS)?;
+                let reg = args[0];
                 let size = args[1];
-                let new_addr = self.realloc(addr as u64, size);
-                self.move_reg(addr as usize, new_addr);
+                //the register holds the address and gets the new one back, size 0 frees the
+                //block and leaves the register alone like FREE does
+                if let Some(new_addr) = self.realloc(self.regs[reg] as u64, size)? {
+                    self.move_reg(reg, new_addr as Register);
+                }
             }
             JZ => {
-                let args = self.get_args(&CONTROL_FLOW_OP_ARGS);
+                let args = self.get_args(&CONTROL_FLOW_OP_ARGS)?;
                 let func = args[0];
                 if self.accumulator == 0 {
-                    self.ip = func;
+                    self.jump(func)?;
                 }
             }
             JNZ => {
-                let args = self.get_args(&CONTROL_FLOW_OP_ARGS);
+                let args = self.get_args(&CONTROL_FLOW_OP_ARGS)?;
                 let func = args[0];
                 if self.accumulator != 0 {
-                    self.ip = func;
+                    self.jump(func)?;
                 }
             }
             DUP => {
                 //takes no arg, duplicates the top of the stack
-                let value = self.stack.peek();
-                self.stack.push(value);
+                let ip = self.op_ip;
+                self.stack.dup().map_err(|fault| VmError::stack(ip, fault))?;
             }
             GT => {
-                let args = self.get_args(&COMPARISON_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = if left > right { 1 } else { 0 };
+                let ord = self.compare()?;
+                self.set_accumulator((ord == Some(Ordering::Greater)) as Register, None);
             }
             LT => {
-                let args = self.get_args(&COMPARISON_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = if left < right { 1 } else { 0 };
+                let ord = self.compare()?;
+                self.set_accumulator((ord == Some(Ordering::Less)) as Register, None);
             }
             EQ => {
-                let args = self.get_args(&COMPARISON_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = if left == right { 1 } else { 0 };
+                let ord = self.compare()?;
+                self.set_accumulator((ord == Some(Ordering::Equal)) as Register, None);
             }
             OR => {
-                let args = self.get_args(&MATH_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = left as u64 | right as u64;
+                let (tp, left, right) = self.typed_pair()?;
+                self.set_accumulator(left | right, Some(tp));
             }
             AND => {
-                let args = self.get_args(&MATH_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = left as u64 & right as u64;
+                let (tp, left, right) = self.typed_pair()?;
+                self.set_accumulator(left & right, Some(tp));
             }
             SWAP => {
                 //swap top 2 elements of the stack
-                let top = self.stack.pop();
-                let next = self.stack.pop();
-                self.stack.push(top);
-                self.stack.push(next);
+                let ip = self.op_ip;
+                self.stack.swap().map_err(|fault| VmError::stack(ip, fault))?;
             }
             XOR => {
-                let args = self.get_args(&MATH_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = left as u64 ^ right as u64;
+                let (tp, left, right) = self.typed_pair()?;
+                self.set_accumulator(left ^ right, Some(tp));
             }
             NEQ => {
-                let args = self.get_args(&COMPARISON_OP_ARGS);
-                let left = args[0];
-                let right = args[1];
-                self.accumulator = if left != right { 1 } else { 0 };
+                let ord = self.compare()?;
+                self.set_accumulator((ord != Some(Ordering::Equal)) as Register, None);
             }
             NOT => {
-                let args = self.get_args(&REG_OP_ARGS);
+                let args = self.get_args(&NOT_OP_ARGS)?;
                 let reg = args[0];
                 self.regs[reg] = !self.regs[reg];
             }
             READ => {
-                let args = self.get_args(&IO_IN_OP_ARGS);
+                let args = self.get_args(&IO_IN_OP_ARGS)?;
                 let buf = args[0];
                 let len = args[1];
-                let data = self.io.read(len);
-                for i in 0..len {
-                    let res = self.heap.write(buf as usize + i, data[i]);
-                }
+                let ip = self.op_ip;
+                let data = self.io.read(len).map_err(|err| VmError::io(ip, err))?;
+                self.store_input(buf, &data)?;
+            }
+            READUNTIL => {
+                let args = self.get_args(&IO_IN_UNTIL_OP_ARGS)?;
+                let (buf, max, delim) = (args[0], args[1], args[2] as u8);
+                let ip = self.op_ip;
+                let data = self.io.read_until(delim, max).map_err(|err| VmError::io(ip, err))?;
+                self.store_input(buf, &data)?;
+            }
+            READLINE => {
+                let args = self.get_args(&IO_IN_OP_ARGS)?;
+                let (buf, max) = (args[0], args[1]);
+                let ip = self.op_ip;
+                let data = self.io.read_until(b'\n', max).map_err(|err| VmError::io(ip, err))?;
+                self.store_input(buf, &data)?;
             }
         };
+        Ok(())
+    }
+    fn decode_op(&self, byte: &Byte) -> Result<Operations, VmError> {
+        let invalid = VmError::InvalidOpcode {
+            ip: self.op_ip,
+            opcode: byte.unwrap(),
+        };
+        match byte.tp {
+            Types::TypeOp if byte.unwrap() <= u8::MAX as u64 => {
+                Operations::try_from(byte.unwrap() as u8).map_err(|_| invalid)
+            }
+            _ => Err(invalid),
+        }
     }
-    fn get_args(&mut self, args: &[ArgType]) -> Vec<usize> {
+    fn get_args(&mut self, args: &[ArgType]) -> Result<Vec<usize>, VmError> {
         let mut regs = Vec::new();
         for arg in args {
             match *arg {
                 //remove reference, reduce indirection
                 Typed => {
-                    let byte = self.read_byte();
-                    let tpr = self.handle_typed(byte);
+                    let byte = self.read_byte()?;
+                    let tpr = self.handle_typed(byte)?;
                     regs.push(tpr);
                 }
                 Untyped => {
-                    let value = self.read_byte().unwrap();
+                    let value = self.read_byte()?.unwrap();
                     regs.push(value as usize);
                 }
                 Dest => {
-                    let reg = self.read_byte().unwrap();
-                    regs.push(reg as usize);
+                    let reg = self.read_byte()?.unwrap();
+                    regs.push(self.check_reg(reg as usize)?);
                 }
                 Func => {
-                    let func = self.read_byte().unwrap();
-                    regs.push(func as usize);
+                    let func = self.read_byte()?;
+                    regs.push(self.resolve_target(&func)?);
                 }
             }
         }
-        regs
+        Ok(regs)
     }
-    fn read_byte(&mut self) -> Byte {
-        if self.ip >= self.data.bytes.len() {
-            self.ip = self.data.bytes.len() - 1;
-        }
-        let byte = self.data.bytes[self.ip].clone();
+    fn read_byte(&mut self) -> Result<Byte, VmError> {
+        let byte = self
+            .data
+            .bytes
+            .get(self.ip)
+            .cloned()
+            .ok_or(VmError::MissingOperand { ip: self.op_ip })?;
         self.ip += 1;
-        byte
+        Ok(byte)
     }
     fn current_byte(&mut self) -> Byte {
         self.data.bytes[self.ip].clone()
@@ -399,31 +674,211 @@ impl Engine {
     fn previous_byte(&mut self) -> Byte {
         self.data.bytes[self.ip - 1].clone()
     }
-    fn handle_typed(&mut self, byte: Byte) -> usize {
+    //read two Typed operands and the type the operation between them should use
+    fn typed_pair(&mut self) -> Result<(NumType, Register, Register), VmError> {
+        let left = self.read_byte()?;
+        let right = self.read_byte()?;
+        let (ltp, rtp) = (self.operand_type(&left), self.operand_type(&right));
+        let tp = NumType::combine(ltp, rtp);
+        let left = self.handle_value(left)?;
+        let right = self.handle_value(right)?;
+        Ok((
+            tp,
+            arith::convert(left, ltp.unwrap_or(tp), tp),
+            arith::convert(right, rtp.unwrap_or(tp), tp),
+        ))
+    }
+    //the result goes in the accumulator with the type the operation was done in
+    fn arith(&mut self, op: ArithOp) -> Result<(), VmError> {
+        let (tp, left, right) = self.typed_pair()?;
+        let value = arith::arith(op, tp, left, right, self.overflow).map_err(|err| match err {
+            ArithError::DivisionByZero => VmError::DivisionByZero { ip: self.op_ip },
+            ArithError::Overflow => VmError::Overflow { ip: self.op_ip },
+        })?;
+        self.set_accumulator(value, Some(tp));
+        Ok(())
+    }
+    fn set_accumulator(&mut self, value: Register, tp: Option<NumType>) {
+        self.accumulator = value;
+        self.accumulator_type = tp;
+    }
+    //the type an operand's value carries, literals have their own and registers and stack slots
+    //have the type of whatever was put in them, heap memory is untyped
+    fn operand_type(&self, byte: &Byte) -> Option<NumType> {
+        let index = byte.unwrap() as usize;
+        match byte.tp {
+            Types::TypeReg => self.regs.types.get(index).copied().flatten(),
+            Types::DerefStack => self.stack.type_at(index),
+            Types::DerefStackReg => {
+                let offset = self.regs.data.get(index).copied()?;
+                self.stack.type_at(offset as usize)
+            }
+            tp => NumType::of(tp),
+        }
+    }
+    //None when a float operand is NaN
+    fn compare(&mut self) -> Result<Option<Ordering>, VmError> {
+        let (tp, left, right) = self.typed_pair()?;
+        Ok(arith::compare(tp, left, right))
+    }
+    //operands used as sizes, addresses and indices
+    fn handle_typed(&mut self, byte: Byte) -> Result<usize, VmError> {
+        Ok(self.handle_value(byte)? as usize)
+    }
+    //the full value of an operand, 128 bit literals and registers keep their high bits
+    fn handle_value(&mut self, byte: Byte) -> Result<Register, VmError> {
         let tp = byte.tp;
-        let byte = byte.unwrap() as usize;
+        let value = byte.wide();
+        let index = byte.unwrap() as usize;
         use Types::*;
-        match tp {
-            TypeU8 => byte,
-            TypeU64 => byte,
-            TypeI128 => byte,
-            TypeU128 => byte,
-            TypeF32 => byte,
-            TypeF64 => byte,
-            DerefStack => self.stack.get(byte) as usize,
+        Ok(match tp {
+            TypeU8 => value,
+            TypeU64 => value,
+            TypeI128 => value,
+            TypeU128 => value,
+            TypeF32 => value,
+            TypeF64 => value,
+            DerefStack => {
+                let ip = self.op_ip;
+                self.stack.get(index).map_err(|fault| VmError::stack(ip, fault))?
+            }
             DerefHeapReg => {
-                let rg = self.regs[byte];
-                self.heap.read(rg as usize, 1).unwrap()[0] as usize
-            }
-            TypeI8 => byte,
-            TypeAddr => byte,
-            TypeReg => self.regs.data[byte] as usize,
-            TypeI64 => byte,
-            NoType => byte,
-            TypeFunc => self.jumptable[byte],
-            TypeJmp => self.jumptable[byte],
-            _ => byte,
+                let rg = self.regs[self.check_reg(index)?];
+                self.heap_read(rg as usize, 1)?[0] as Register
+            }
+            DerefStackReg => {
+                //the register holds the slot offset, counted down from the top like DerefStack
+                let offset = self.regs[self.check_reg(index)?] as usize;
+                let ip = self.op_ip;
+                self.stack.get(offset).map_err(|fault| VmError::stack(ip, fault))?
+            }
+            DerefIndexed => {
+                let operand = Indexed::unpack(value);
+                if !operand.valid_scale() {
+                    return Err(VmError::BadScale {
+                        ip: self.op_ip,
+                        scale: operand.scale,
+                    });
+                }
+                let base = self.regs[self.check_reg(operand.base as usize)?] as u64;
+                let offset = self.regs[self.check_reg(operand.index as usize)?] as u64;
+                let addr = base
+                    .wrapping_add(offset.wrapping_mul(operand.scale as u64))
+                    .wrapping_add(operand.disp as u64);
+                let data = self.heap_read(addr as usize, operand.scale as usize)?;
+                let mut value = [0; 16];
+                value[..data.len()].copy_from_slice(&data);
+                Register::from_le_bytes(value)
+            }
+            TypeI8 => value,
+            TypeAddr => value,
+            TypeReg => self.regs.data[self.check_reg(index)?],
+            TypeI64 => value,
+            NoType => value,
+            TypeFunc | TypeJmp => self.resolve_target(&byte)? as Register,
+            _ => value,
+        })
+    }
+    //turn a jump or call operand into an index in the bytestream
+    //named functions are looked up, jumps (resolved labels) and anything else are used as is
+    fn resolve_target(&self, byte: &Byte) -> Result<usize, VmError> {
+        let value = byte.unwrap();
+        let target = match byte.tp {
+            Types::TypeFunc => self.functions.get(&value).copied(),
+            _ => Some(value as usize),
+        };
+        target.ok_or(VmError::BadJump {
+            ip: self.op_ip,
+            target: value as usize,
+        })
+    }
+    //push a frame returning to the next instruction and jump to target
+    fn call(&mut self, target: usize) -> Result<(), VmError> {
+        let args = self.arity.get(&target).copied().unwrap_or(0);
+        let frame = Frame {
+            ret: self.ip,
+            target,
+            args: ARG_REGS.take(args).map(|reg| self.regs[reg]).collect(),
+            base: self.stack.pointer(),
+            saved: SAVED_REGS.map(|reg| (reg, self.regs[reg], self.regs.types[reg])).collect(),
+        };
+        if !self.callstack.push(frame) {
+            return Err(VmError::LimitExceeded {
+                ip: self.op_ip,
+                limit: Limit::CallDepth,
+            });
         }
+        self.jump(target)
+    }
+    fn call_native(&mut self, packed: u64) -> Result<(), VmError> {
+        let name = format::func_name(packed);
+        let native = match self.natives.get_mut(&packed) {
+            Some(native) => native,
+            None => {
+                return Err(VmError::UnknownNative {
+                    ip: self.op_ip,
+                    name,
+                })
+            }
+        };
+        let mut ctx = EngineCtx {
+            ip: self.op_ip,
+            name: &name,
+            regs: &mut self.regs,
+            accumulator: &mut self.accumulator,
+            accumulator_type: &mut self.accumulator_type,
+            heap: &mut self.heap,
+        };
+        native(&mut ctx)
+    }
+    //pop the current frame, dropping its stack and restoring saved registers
+    fn ret(&mut self) -> Result<(), VmError> {
+        let ip = self.op_ip;
+        let frame = self.callstack.pop().ok_or(VmError::StackUnderflow { ip })?;
+        self.stack.unwind(frame.base);
+        for (reg, value, tp) in frame.saved {
+            self.regs.set(reg, value, tp);
+        }
+        self.ip = frame.ret;
+        Ok(())
+    }
+    fn jump(&mut self, target: usize) -> Result<(), VmError> {
+        if target > self.data.bytes.len() {
+            return Err(VmError::BadJump {
+                ip: self.op_ip,
+                target,
+            });
+        }
+        self.ip = target;
+        Ok(())
+    }
+    fn check_reg(&self, reg: usize) -> Result<reg_t, VmError> {
+        if reg < self.regs.data.len() {
+            Ok(reg)
+        } else {
+            Err(VmError::BadRegister { ip: self.op_ip, reg })
+        }
+    }
+    //copy input to the heap and leave how many bytes there were in the accumulator, which is only
+    //0 at the end of the input (or when 0 bytes were asked for)
+    fn store_input(&mut self, buf: usize, data: &[u8]) -> Result<(), VmError> {
+        for (i, byte) in data.iter().enumerate() {
+            self.heap_write(buf.wrapping_add(i), *byte)?;
+        }
+        self.set_accumulator(data.len() as Register, None);
+        Ok(())
+    }
+    fn heap_read(&self, addr: usize, size: usize) -> Result<Vec<u8>, VmError> {
+        let ip = self.op_ip;
+        self.heap
+            .read(addr, size)
+            .map_err(|fault| VmError::heap(ip, fault))
+    }
+    fn heap_write(&mut self, addr: usize, data: u8) -> Result<(), VmError> {
+        let ip = self.op_ip;
+        self.heap
+            .write(addr, data)
+            .map_err(|fault| VmError::heap(ip, fault))
     }
 }
 
@@ -438,3 +893,78 @@ pub fn hextostring(hex: u64) -> Vec<u8> {
     }
     result.bytes().collect::<Vec<u8>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::capture::{run_captured, RunOutput};
+    use super::*;
+    use crate::asm::rvasm;
+    use crate::builder::program::{Operand, ProgramBuilder};
+
+    fn run(asm: &str) -> RunOutput {
+        run_captured(rvasm(asm).expect("program should assemble"), b"")
+    }
+
+    #[test]
+    fn push_pushes_the_value_of_its_operand() {
+        let output = run("MOV [1] 64u7\nPUSH [1]\nPUSH 64u9\nPOP [2]\nPOP [3]\n");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!((output.registers[2], output.registers[3]), (9, 7));
+        let output = run("POP [1]\n");
+        assert_eq!(output.status, Err(VmError::StackUnderflow { ip: 0 }));
+    }
+
+    #[test]
+    fn dec_and_not_take_one_register() {
+        let output = run("MOV [1] 64u5\nDEC [1]\nNOT [2]\nMOV [3] 64u1\n");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!(output.instructions, 4);
+        assert_eq!(output.registers[1], 4);
+        assert_eq!(output.registers[2], !0);
+        assert_eq!(output.registers[3], 1);
+    }
+
+    #[test]
+    fn inc_adds_its_amount_to_registers_and_heap_bytes() {
+        let program = ProgramBuilder::new()
+            .alloc(Operand::reg(1), Operand::u64(1))
+            .inc(Operand::addr(0), Operand::u64(5))
+            .inc(Operand::addr(0), Operand::u64(0xff))
+            .mov(Operand::reg(2), Operand::heap_reg(1))
+            .inc(Operand::reg(3), Operand::u64(5))
+            .build()
+            .unwrap();
+        let output = run_captured(program, b"");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!(output.registers[2], 4);
+        assert_eq!(output.registers[3], 5);
+    }
+
+    #[test]
+    fn reading_freed_memory_faults() {
+        let output = run("ALLOC [1] 64u8\nFREE [1]\nMOV [2] h1\n");
+        let fault = VmError::HeapFault { ip: 5, addr: 0, op: memory::HeapOp::Read };
+        assert_eq!(output.status, Err(fault));
+    }
+
+    #[test]
+    fn loading_a_program_starts_it_from_a_clean_engine() {
+        let first = "JMP &main\nFUNC :f\nPUSH 64u9\nALLOC [2] 64u8\nHALT 64u1\n\
+                     main:\nMOV [1] 64u5\nADD [1] [1]\nCALL :f\n";
+        let mut engine = Engine::with_config(EngineConfig {
+            fuel: Some(100),
+            ..EngineConfig::default()
+        });
+        assert_eq!(engine.run(rvasm(first).unwrap()), Ok(ExitStatus::Halted(1)));
+        assert_eq!((engine.regs.data[1], engine.accumulator()), (5, 10));
+        assert_eq!((engine.stack().len(), engine.call_depth()), (1, 1));
+        assert_eq!(engine.heap.stats().allocated_blocks, 1);
+        assert!(engine.fuel() < Some(100));
+        engine.load(rvasm("NOP\n").unwrap()).unwrap();
+        assert_eq!((engine.regs.data[1], engine.accumulator()), (0, 0));
+        assert_eq!((engine.stack().len(), engine.call_depth()), (0, 0));
+        assert_eq!(engine.heap.stats().allocated_blocks, 0);
+        assert_eq!((engine.fuel(), engine.fuel_consumed()), (Some(100), 0));
+        assert_eq!(engine.resume(), Ok(ExitStatus::Finished));
+    }
+}
@@ -0,0 +1,79 @@
+//host functions, rust code registered with Engine::register_native and called by NATIVE :name
+//natives follow the calling convention, arguments come in the argument registers and the result
+//goes in the accumulator
+use crate::engine::arith::NumType;
+use crate::engine::memory::Heap;
+use crate::engine::regs::{Registers, ARG_REGS};
+use crate::engine::VmError;
+
+pub type Native = Box<dyn FnMut(&mut EngineCtx) -> Result<(), VmError> + Send>;
+
+//what a native can see of the engine while it runs
+pub struct EngineCtx<'a> {
+    //ip of the NATIVE instruction, used for errors
+    pub(crate) ip: usize,
+    pub(crate) name: &'a str,
+    pub(crate) regs: &'a mut Registers,
+    pub(crate) accumulator: &'a mut u128,
+    pub(crate) accumulator_type: &'a mut Option<NumType>,
+    pub(crate) heap: &'a mut Heap,
+}
+
+impl EngineCtx<'_> {
+    //the name the native was called by
+    pub fn name(&self) -> &str {
+        self.name
+    }
+    //argument index, counting from 0
+    pub fn arg(&self, index: usize) -> Result<u128, VmError> {
+        if index >= ARG_REGS.len() {
+            return Err(self.error(format!("argument {} is past the last argument register", index)));
+        }
+        self.reg(ARG_REGS.start + index)
+    }
+    pub fn reg(&self, reg: usize) -> Result<u128, VmError> {
+        match self.regs.data.get(reg) {
+            Some(value) => Ok(*value),
+            None => Err(VmError::BadRegister { ip: self.ip, reg }),
+        }
+    }
+    //values from the host are untyped, like values loaded from the heap
+    pub fn set_reg(&mut self, reg: usize, value: u128) -> Result<(), VmError> {
+        match reg < self.regs.data.len() {
+            true => {
+                self.regs.set(reg, value, None);
+                Ok(())
+            }
+            false => Err(VmError::BadRegister { ip: self.ip, reg }),
+        }
+    }
+    pub fn accumulator(&self) -> u128 {
+        *self.accumulator
+    }
+    //set the result of the native
+    pub fn set_accumulator(&mut self, value: u128) {
+        *self.accumulator = value;
+        *self.accumulator_type = None;
+    }
+    pub fn read(&self, addr: usize, len: usize) -> Result<Vec<u8>, VmError> {
+        self.heap
+            .read(addr, len)
+            .map_err(|fault| VmError::heap(self.ip, fault))
+    }
+    pub fn write(&mut self, addr: usize, data: &[u8]) -> Result<(), VmError> {
+        for (i, byte) in data.iter().enumerate() {
+            self.heap
+                .write(addr.wrapping_add(i), *byte)
+                .map_err(|fault| VmError::heap(self.ip, fault))?;
+        }
+        Ok(())
+    }
+    //an error to return from the native, reported with the native's name and ip
+    pub fn error(&self, message: impl Into<String>) -> VmError {
+        VmError::Native {
+            ip: self.ip,
+            name: self.name.to_string(),
+            message: message.into(),
+        }
+    }
+}
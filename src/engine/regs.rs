@@ -1,10 +1,29 @@
+use super::arith::NumType;
+use core::ops::Range;
+
+pub const REGISTER_COUNT: usize = 60;
+//calling convention: arguments are passed in r1 to r8 and the result is left in the accumulator
+pub const ARG_REGS: Range<usize> = 1..9;
+//r16 to r31 are saved by CALL and put back by RET, so a function can use them freely
+pub const SAVED_REGS: Range<usize> = 16..32;
+
 #[derive(Debug, Clone, Copy)]
 pub struct Registers {
-    pub data: [u64; 60],
+    //wide enough for 128 bit values, 64 bit results are zero extended
+    pub data: [u128; REGISTER_COUNT],
+    //the type of the value each register holds, None for values from memory or the host
+    pub(crate) types: [Option<NumType>; REGISTER_COUNT],
+}
+
+impl Registers {
+    pub(crate) fn set(&mut self, reg: usize, value: u128, tp: Option<NumType>) {
+        self.data[reg] = value;
+        self.types[reg] = tp;
+    }
 }
 
 impl core::ops::Index<usize> for Registers {
-    type Output = u64;
+    type Output = u128;
 
     fn index(&self, index: usize) -> &Self::Output {
         &self.data[index]
@@ -17,6 +36,47 @@ impl core::ops::IndexMut<usize> for Registers {
 }
 impl Default for Registers {
     fn default() -> Self {
-        Self { data: [0; 60] }
+        Self {
+            data: [0; REGISTER_COUNT],
+            types: [None; REGISTER_COUNT],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asm::rvasm;
+    use crate::engine::capture::run_captured;
+    use crate::engine::{ExitStatus, VmError};
+
+    #[test]
+    fn arguments_come_in_and_the_result_goes_out_in_the_accumulator() {
+        let asm = "JMP &main\n.func add(a, b)\n    ADD %a %b\n    RET\nmain:\n\
+                   MOV [1] 64u2\nMOV [2] 64u3\nCALL :add\n";
+        let output = run_captured(rvasm(asm).unwrap(), b"");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        assert_eq!(output.accumulator, 5);
+    }
+
+    #[test]
+    fn ret_restores_the_saved_registers_only() {
+        //register numbers are hex, so this is r15, r16, r31 and r32
+        let asm = "JMP &main\n.func clobber()\n\
+                   MOV [f] 64u0\n    MOV [10] 64u0\n\
+                   MOV [1f] 64u0\n    MOV [20] 64u0\n    RET\n\
+                   main:\nMOV [f] 64u1\nMOV [10] 64u2\nMOV [1f] 64u3\nMOV [20] 64u4\nCALL :clobber\n";
+        let output = run_captured(rvasm(asm).unwrap(), b"");
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        let regs = output.registers;
+        assert_eq!((regs[15], regs[16], regs[31], regs[32]), (0, 2, 3, 0));
+    }
+
+    #[test]
+    fn ret_drops_what_the_function_left_on_the_stack() {
+        let asm = "JMP &main\n.func locals()\n    PUSH 64u2\n    PUSH 64u3\n    RET\n\
+                   main:\nPUSH 64u1\nCALL :locals\nPOP [1]\nPOP [2]\n";
+        let output = run_captured(rvasm(asm).unwrap(), b"");
+        assert!(matches!(output.status, Err(VmError::StackUnderflow { .. })));
+        assert_eq!(output.registers[1], 1);
     }
 }
@@ -1,57 +1,241 @@
+//where READ gets its input and WRITE and FLUSH send their output
+//the engine holds any Io, real stdin and stdout by default
 extern crate alloc;
-use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use std::io::{Read, Write};
+use std::sync::{Mutex, MutexGuard};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
 
-pub struct IO {
-    pub in_buffer: Vec<u8>,
-    pub out_buffer: Vec<u8>,
+pub trait Io: Send {
+    //output from WRITE, it may be held back until flush
+    fn write(&mut self, data: &[u8]) -> io::Result<()>;
+    //up to len bytes of input, waiting for them if need be, fewer only at the end of the input
+    fn read(&mut self, len: usize) -> io::Result<Vec<u8>>;
+    //bytes up to and including delim, at most max of them, fewer only at the end of the input
+    fn read_until(&mut self, delim: u8, max: usize) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        while data.len() < max {
+            match self.read(1)?.first() {
+                Some(&byte) => {
+                    data.push(byte);
+                    if byte == delim {
+                        break;
+                    }
+                }
+                None => break,
+            }
+        }
+        Ok(data)
+    }
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+//input from any reader, output buffered until flush and then written to any writer
+pub struct StreamIo {
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+    buffer: Vec<u8>,
+}
+
+impl StreamIo {
+    pub fn new(input: impl BufRead + Send + 'static, output: impl Write + Send + 'static) -> StreamIo {
+        StreamIo {
+            input: Box::new(input),
+            output: Box::new(output),
+            buffer: Vec::new(),
+        }
+    }
+    pub fn stdio() -> StreamIo {
+        StreamIo::new(StdinLines::default(), io::stdout())
+    }
+    //read from and write to files, stdin or stdout are used for the ones not given
+    pub fn files(input: Option<&str>, output: Option<&str>) -> io::Result<StreamIo> {
+        let input: Box<dyn BufRead + Send> = match input {
+            Some(path) => Box::new(BufReader::new(File::open(path)?)),
+            None => Box::new(StdinLines::default()),
+        };
+        let output: Box<dyn Write + Send> = match output {
+            Some(path) => Box::new(File::create(path)?),
+            None => Box::new(io::stdout()),
+        };
+        Ok(StreamIo {
+            input,
+            output,
+            buffer: Vec::new(),
+        })
+    }
 }
-impl IO {
-    pub fn write(&mut self, data: &[u8]) {
-        self.out_buffer.extend_from_slice(data);
+
+impl Io for StreamIo {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffer.extend_from_slice(data);
+        Ok(())
     }
-    pub fn read(&mut self, size: usize) -> Vec<u8> {
+    fn read(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        //len comes from the program, so the buffer only grows as input actually arrives
         let mut data = Vec::new();
-        for _ in 0..size {
-            data.push(self.in_buffer.remove(0));
-        }
-        data
+        (&mut self.input).take(len as u64).read_to_end(&mut data)?;
+        Ok(data)
     }
-    pub fn read_until(&mut self, delim: u8) -> Vec<u8> {
+    fn read_until(&mut self, delim: u8, max: usize) -> io::Result<Vec<u8>> {
         let mut data = Vec::new();
-        loop {
-            let byte = self.in_buffer.remove(0);
-            if byte == delim {
-                break;
-            }
-            data.push(byte);
-        }
-        data
-    }
-    pub fn read_line(&mut self) -> String {
-        let mut data = String::new();
-        loop {
-            let byte = self.in_buffer.remove(0);
-            if byte == b'\n' {
-                break;
-            }
-            data.push(byte as char);
-        }
-        data
+        (&mut self.input).take(max as u64).read_until(delim, &mut data)?;
+        Ok(data)
     }
-    pub fn flush(&mut self) {
-        //write to stdout then clear
-        std::io::stdout().write_all(&self.out_buffer).unwrap();
-        self.out_buffer.clear();
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.write_all(&self.buffer)?;
+        self.buffer.clear();
+        self.output.flush()
     }
 }
 
-impl Default for IO {
+impl Default for StreamIo {
     fn default() -> Self {
-        Self {
-            in_buffer: Vec::new(),
-            out_buffer: Vec::new(),
+        StreamIo::stdio()
+    }
+}
+
+//stdin a line at a time, it is only locked while a line is read and nothing past that line is
+//buffered, so cbvm debug can read commands from stdin while the program reads its input from it
+#[derive(Debug, Default)]
+pub struct StdinLines {
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for StdinLines {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for StdinLines {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.line.len() {
+            self.line.clear();
+            self.pos = 0;
+            io::stdin().lock().read_until(b'\n', &mut self.line)?;
         }
+        Ok(&self.line[self.pos..])
+    }
+    fn consume(&mut self, amount: usize) {
+        self.pos = (self.pos + amount).min(self.line.len());
+    }
+}
+
+//input and output kept in memory, clones share the same buffers so the host can keep one to fill
+//the input and look at the output while the engine holds another
+#[derive(Debug, Clone, Default)]
+pub struct MemoryIo {
+    buffers: Arc<Mutex<Buffers>>,
+}
+
+#[derive(Debug, Default)]
+struct Buffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+}
+
+impl MemoryIo {
+    pub fn new(input: &[u8]) -> MemoryIo {
+        let io = MemoryIo::default();
+        io.push_input(input);
+        io
+    }
+    //add more input after what is left
+    pub fn push_input(&self, data: &[u8]) {
+        self.buffers().input.extend(data);
+    }
+    //everything written so far
+    pub fn output(&self) -> Vec<u8> {
+        self.buffers().output.clone()
+    }
+    pub fn take_output(&self) -> Vec<u8> {
+        std::mem::take(&mut self.buffers().output)
+    }
+    //the buffers are plain bytes, so they are still usable if a holder panicked
+    fn buffers(&self) -> MutexGuard<'_, Buffers> {
+        self.buffers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Io for MemoryIo {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.buffers().output.extend_from_slice(data);
+        Ok(())
+    }
+    fn read(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        let mut buffers = self.buffers();
+        let len = len.min(buffers.input.len());
+        Ok(buffers.input.drain(..len).collect())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::rvasm;
+    use crate::engine::capture::run_captured;
+    use crate::engine::ExitStatus;
+
+    #[test]
+    fn huge_reads_stop_at_the_end_of_the_input() {
+        let mut io = StreamIo::new(&b"abc"[..], io::sink());
+        assert_eq!(io.read(usize::MAX).unwrap(), b"abc");
+        assert_eq!(io.read(usize::MAX).unwrap(), b"");
+        let mut io = MemoryIo::new(b"abc");
+        assert_eq!(io.read(usize::MAX).unwrap(), b"abc");
+    }
+
+    #[test]
+    fn read_until_stops_at_the_delimiter_or_max() {
+        let mut io = StreamIo::new(&b"ab\ncd"[..], io::sink());
+        assert_eq!(io.read_until(b'\n', usize::MAX).unwrap(), b"ab\n");
+        assert_eq!(io.read_until(b'\n', 1).unwrap(), b"c");
+        assert_eq!(io.read_until(b'\n', usize::MAX).unwrap(), b"d");
+        let mut io = MemoryIo::new(b"ab\ncd");
+        assert_eq!(io.read_until(b'\n', usize::MAX).unwrap(), b"ab\n");
+        assert_eq!(io.read_until(b'\n', 1).unwrap(), b"c");
+    }
+
+    //run op three times over the input, keeping the accumulator after each in r2, r3 and r4
+    fn three_reads(op: &str, input: &[u8]) -> ([u128; 3], Vec<u8>) {
+        let asm = format!(
+            "ALLOC [1] 64u10\n{op}\nREACC [2]\n{op}\nREACC [3]\n{op}\nREACC [4]\nWRITE [1] 64u2\n",
+            op = op
+        );
+        let output = run_captured(rvasm(&asm).unwrap(), input);
+        assert_eq!(output.status, Ok(ExitStatus::Finished));
+        let regs = output.registers;
+        ([regs[2], regs[3], regs[4]], output.stdout)
+    }
+
+    #[test]
+    fn readline_reports_the_length_read_and_0_at_the_end() {
+        //the last line has no newline, then there is nothing left
+        let (lengths, stdout) = three_reads("READLINE [1] 64u10", b"ab\ncd");
+        assert_eq!(lengths, [3, 2, 0]);
+        assert_eq!(stdout, b"cd");
+        //a line longer than the buffer is read in pieces
+        let (lengths, _) = three_reads("READLINE [1] 64u2", b"abcd\n");
+        assert_eq!(lengths, [2, 2, 1]);
+    }
+
+    #[test]
+    fn readuntil_stops_after_the_delimiter_and_at_the_end() {
+        let (lengths, stdout) = three_reads("READUNTIL [1] 64u10 64u2c", b"a,bc");
+        assert_eq!(lengths, [2, 2, 0]);
+        assert_eq!(stdout, b"bc");
+        let (lengths, _) = three_reads("READ [1] 64u3", b"abcd");
+        assert_eq!(lengths, [3, 1, 0]);
     }
 }
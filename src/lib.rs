@@ -13,6 +13,7 @@ pub mod builder;
 pub mod bytecode;
 pub mod reader;
 pub mod asm;
+pub mod verify;
 use bytecode::{data::ByteData, ops::ArgType::*, ops::Operations::*, types::Types};
 pub mod engine;
 use builder::bytes::*;
@@ -22,18 +23,20 @@ use std::str::from_utf8_unchecked;
 use std::time::Instant;
 use std::{env, string};
 
-pub fn read (path: &str) -> Vec<u8> {
+pub fn read (path: &str) -> Result<Vec<u8>, bytecode::format::FormatError> {
     let mut reader = Reader::new(path);
     reader.read();
-    reader.group();
-    reader.bytes.into()
+    reader.group().map(|bytes| bytes.into())
 }
 
-pub fn run(bytes: Vec<u8>) {
+pub fn run(bytes: Vec<u8>) -> Result<engine::ExitStatus, engine::VmError> {
+    let bytes = ByteStream::try_from(bytes).map_err(engine::VmError::Format)?;
     let mut engine = engine::Engine::new();
     let start = Instant::now();
-    engine.run(bytes.into());
+    let status = engine.run(bytes);
     let duration = start.elapsed();
     println!("\nTime elapsed in running VM is: {:?}", duration);
+    status
 }
 
+pub use engine::capture::{run_captured, run_captured_with, RunOutput};
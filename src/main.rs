@@ -23,6 +23,7 @@ use std::str::from_utf8_unchecked;
 use std::time::Instant;
 use std::{env, string};
 pub mod asm;
+pub mod verify;
 
 
 fn main() {
@@ -33,13 +34,14 @@ fn main() {
         help();
         return;
     }
-    let cmds = vec![
+    let cmds = [
         "run".to_string(),
         "debug".to_string(),
         "help".to_string(),
         "view".to_string(),
         "asm".to_string(),
-    
+        "assemble".to_string(),
+        "verify".to_string(),
     ];
     //check first arg to be in list of cmds
     if cmds.contains(&args[1]) {
@@ -49,6 +51,8 @@ fn main() {
             "help" => help(),
             "view" => view(),
             "asm" => asm(),
+            "assemble" => assemble(),
+            "verify" => verify(),
             _ => println!("Invalid command"),
         }
     } else {
@@ -56,54 +60,217 @@ fn main() {
     }
 }
 
+//read and decode a .cbvm file, exiting with a message if it is malformed
+fn load(path: &str) -> ByteStream {
+    let mut reader = Reader::new(path);
+    reader.read();
+    match reader.group() {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            println!("Invalid bytecode in {}: {}", path, err);
+            std::process::exit(1);
+        }
+    }
+}
+
 //run function, take path from cli, read file, group bytes, and run vm
 fn run() {
     let args: Vec<String> = env::args().collect();
-    let mut reader = Reader::new(&args[2]);
-    reader.read();
-    reader.group();
-    let mut engine = engine::Engine::new();
+    let bytes = load(&args[2]);
+    let defaults = engine::EngineConfig::default();
+    let config = engine::EngineConfig {
+        heap_size: flag(&args, "--heap").unwrap_or(defaults.heap_size),
+        stack_slots: flag(&args, "--stack").unwrap_or(defaults.stack_slots),
+        max_call_depth: flag(&args, "--call-depth").unwrap_or(defaults.max_call_depth),
+        max_instructions: flag(&args, "--max-instructions").map(|max| max as u64),
+        max_output: flag(&args, "--max-output"),
+        fuel: flag(&args, "--fuel").map(|fuel| fuel as u64),
+    };
+    let mut engine = engine::Engine::with_config(config);
+    let input = text_flag(&args, "--in");
+    let output = text_flag(&args, "--out");
+    if input.is_some() || output.is_some() {
+        match engine::StreamIo::files(input, output) {
+            Ok(io) => engine.set_io(Box::new(io)),
+            Err(err) => {
+                println!("Cannot open io files: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    engine.set_verify(args.iter().any(|arg| arg == "--verify"));
     let start = Instant::now();
-    engine.run(reader.bytes);
+    let status = engine.run(bytes);
     let duration = start.elapsed();
+    report(status);
+}
+
+//the number after a flag such as --heap 4096, exiting with a message if it is missing or invalid
+fn flag(args: &[String], name: &str) -> Option<usize> {
+    let i = args.iter().position(|arg| arg == name)?;
+    match args.get(i + 1).map(|value| value.parse()) {
+        Some(Ok(value)) => Some(value),
+        _ => {
+            println!("{} needs a number", name);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn text_flag<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let i = args.iter().position(|arg| arg == name)?;
+    match args.get(i + 1) {
+        Some(value) => Some(value),
+        None => {
+            println!("{} needs a path", name);
+            std::process::exit(1);
+        }
+    }
 }
 
 fn asm () {
     let args: Vec<String> = env::args().collect();
-    let mut reader = Reader::new(&args[2]);
-    reader.read();
-    reader.group();
-    println!("{}", asm::mkasm(reader.bytes));
+    let bytes = load(&args[2]);
+    println!("{}", asm::mkasm(bytes));
+}
+
+//assemble function, parse an asm file and write it out as bytecode
+//usage: assemble <in.asm> [-o <out.cbvm>], the output defaults to the input with a .cbvm extension
+fn assemble() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        help();
+        return;
+    }
+    let input = &args[2];
+    let output = match args.iter().position(|arg| arg == "-o") {
+        Some(i) => match args.get(i + 1) {
+            Some(path) => path.clone(),
+            None => {
+                println!("-o needs a path");
+                std::process::exit(1);
+            }
+        },
+        None => std::path::Path::new(input)
+            .with_extension("cbvm")
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let source = match std::fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(err) => {
+            println!("Cannot read {}: {}", input, err);
+            std::process::exit(1);
+        }
+    };
+    match asm::rvasm(&source) {
+        Ok(bytes) => {
+            if let Err(err) = bytes.save(&output) {
+                println!("Cannot write {}: {}", output, err);
+                std::process::exit(1);
+            }
+        }
+        Err(errors) => {
+            for err in errors {
+                println!("{}: {}", input, err);
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+//verify function, check a file without running it and print every problem found
+fn verify() {
+    let args: Vec<String> = env::args().collect();
+    let bytes = load(&args[2]);
+    match verify::verify(&bytes) {
+        Ok(()) => println!("{}: ok", args[2]),
+        Err(errors) => {
+            for err in &errors {
+                println!("{}: {}", args[2], err);
+            }
+            println!("{} problem(s) found", errors.len());
+            std::process::exit(1);
+        }
+    }
 }
 
 //debug function, take path from cli, read file, group bytes, and run vm with debug
 fn debug() {
     let args: Vec<String> = env::args().collect();
-    let mut reader = Reader::new(&args[2]);
-    reader.read();
-    reader.group();
+    let bytes = load(&args[2]);
     let mut engine = engine::Engine::new();
     let start = Instant::now();
-    engine.debug(reader.bytes);
+    let status = engine.debug(bytes);
     let duration = start.elapsed();
+    report(status);
+}
+
+//print a vm error in the same style segfaults used to be printed, then exit with the program's
+//exit code (0 to 127), or the fault's own code (200 and up) so scripts can tell faults apart
+fn report(status: Result<engine::ExitStatus, engine::VmError>) {
+    let red = "\x1b[31m";
+    let reset = "\x1b[0m";
+    match status {
+        Err(err) => {
+            println!("{}Error{}: {}", red, reset, err);
+            std::process::exit(err.exit_code());
+        }
+        Ok(engine::ExitStatus::OutOfFuel) => {
+            println!("{}Error{}: ran out of fuel", red, reset);
+            std::process::exit(engine::ExitStatus::OutOfFuel.code());
+        }
+        Ok(status) => std::process::exit(status.code()),
+    }
 }
 
 //help function, print help
 fn help() {
     println!("Commands:");
-    println!("run <path> - run vm");
-    println!("debug <path> - run vm with debug");
+    println!("run <path> [--verify] - run vm, optionally verifying the program first");
+    println!("    limits: --heap <bytes> --stack <slots> --call-depth <n> --max-instructions <n> --max-output <bytes>");
+    println!("    --in <path> --out <path> - read input from and write output to files instead of stdin and stdout");
+    println!("    --fuel <n> - stop once n fuel is used, instructions on the heap, io and calls cost more");
+    println!("debug <path> - step through the program in the debugger");
     println!("help - print help");
     println!("view <path> - view bytecode");
     println!("asm <path> - view asm");
+    println!("assemble <in.asm> -o <out.cbvm> - assemble asm into bytecode");
+    println!("verify <path> - check bytecode without running it");
 }
 
 
-//view function, take path from cli, read file, group bytes, and print bytes
+//view function, take path from cli, validate the header, print a summary of it and the bytes
 fn view() {
     let args: Vec<String> = env::args().collect();
     let mut reader = Reader::new(&args[2]);
     reader.read();
-    reader.group();
-    println!("{}", reader.bytes);
-}
\ No newline at end of file
+    let header = match reader.validate() {
+        Ok(header) => header.clone(),
+        Err(err) => {
+            println!("Invalid bytecode in {}: {}", args[2], err);
+            std::process::exit(1);
+        }
+    };
+    print!("{}", header);
+    match reader.symbols() {
+        Ok(symbols) if !symbols.is_empty() => {
+            println!("symbols:");
+            for symbol in symbols {
+                match symbol.args {
+                    Some(args) => println!("  {:<8} {} (args: {})", symbol.name, symbol.index, args),
+                    None => println!("  {:<8} {}", symbol.name, symbol.index),
+                }
+            }
+        }
+        Ok(_) => (),
+        Err(err) => println!("symbols: {}", err),
+    }
+    match reader.group() {
+        Ok(bytes) => println!("code:\n{}", bytes),
+        Err(err) => {
+            println!("Invalid bytecode in {}: {}", args[2], err);
+            std::process::exit(1);
+        }
+    }
+}
@@ -4,6 +4,8 @@ use crate::{
     byte,
     bytecode::{
         data::ByteData,
+        encoding,
+        format::{self, FormatError, Header, SectionKind, Symbol},
         ops::ArgType::*,
         ops::Operations::*,
         ops::*,
@@ -22,6 +24,7 @@ pub struct Reader {
     file: File,
     pub stream: Vec<u8>,
     pub bytes: ByteStream,
+    pub header: Option<Header>,
 }
 
 impl Reader {
@@ -31,51 +34,37 @@ impl Reader {
             file: File::open(path).unwrap(),
             stream: Vec::new(),
             bytes: ByteStream::new(),
+            header: None,
         }
     }
-    pub fn new_read(data: &Vec<u8>) -> Reader {
-        Reader {
-            pos: 0,
-            file: File::open("temp").unwrap_or(File::create("temp").unwrap()),
-            stream: data.clone(),
-            bytes: ByteStream::new(),
-        }
-    }
-    pub fn read(&mut self) -> () {
+    pub fn read(&mut self) {
         let mut data = Vec::new();
         self.file.read_to_end(&mut data).unwrap();
         self.stream = data;
     }
-    pub fn group(&mut self) -> ByteStream {
-        while self.pos < self.stream.len() {
-            self.handle(self.stream[self.pos]);
-            self.pos += 1;
-        }
-        self.bytes.clone()
+    //check the magic number, version and section table before anything is decoded
+    pub fn validate(&mut self) -> Result<&Header, FormatError> {
+        let header = Header::parse(&self.stream)?;
+        Ok(self.header.insert(header))
     }
-    //function to handle a number, take the number of arguments from the contant in ops and add to bytestream
-    fn handle(&mut self, n: u8) {
-        let tp = Types::from(n);
-        self.next();
-        let arg = self.view() as u64;
-        let byte = Byte{
-            data: Box::new(arg),
-            pos: 0,
-            tp: tp,
+    //decode every type tag and its data in the code section into the bytestream
+    pub fn group(&mut self) -> Result<ByteStream, FormatError> {
+        let header = match &self.header {
+            Some(header) => header.clone(),
+            None => self.validate()?.clone(),
         };
-        self.bytes.emit(byte);
+        self.bytes = format::decode_code(&self.stream, &header)?;
+        Ok(self.bytes.clone())
     }
-    //functions to view current item in data, next, and increase counter of position
-    fn view(&self) -> u8 {
-        self.stream[self.pos]
+    //raw contents of a section, if the file has one
+    pub fn section(&self, kind: SectionKind) -> Option<&[u8]> {
+        let section = self.header.as_ref()?.section(kind)?;
+        Some(&self.stream[section.range()])
     }
-    fn next(&mut self) {
-        if self.pos + 1 >= self.stream.len() {
-            return;
+    pub fn symbols(&self) -> Result<Vec<Symbol>, FormatError> {
+        match self.section(SectionKind::Symbols) {
+            Some(section) => format::decode_symbols(section),
+            None => Ok(Vec::new()),
         }
-        self.pos += 1;
-    }
-    fn peek(&self) -> u8 {
-        self.stream[self.pos + 1]
     }
 }
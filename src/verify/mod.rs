@@ -0,0 +1,253 @@
+//static checks over a bytestream before it is run, the program is walked once and every problem
+//is collected so they can all be reported together
+use crate::builder::bytes::{Byte, ByteStream};
+use crate::bytecode::format::{func_name, symbols};
+use crate::bytecode::ops::Operations::{self, *};
+use crate::bytecode::types::Indexed;
+use crate::bytecode::types::Types::{self, *};
+use crate::engine::REGISTER_COUNT;
+use std::fmt;
+
+//every error carries the index of the instruction it was found in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    //a byte where an op should start is not a known opcode
+    InvalidOpcode { at: usize, tp: Types, value: u64 },
+    MissingOperand { at: usize, op: Operations, index: usize },
+    ExtraOperand { at: usize, op: Operations },
+    BadOperand { at: usize, op: Operations, index: usize, found: Types },
+    BadRegister { at: usize, reg: u64 },
+    //an indexed operand scales by something other than 1, 2, 4 or 8
+    BadScale { at: usize, scale: u8 },
+    //a jump, call or the entry point does not land on the start of an instruction
+    BadJump { at: usize, target: u64 },
+    UndefinedFunction { at: usize, name: String },
+}
+
+impl VerifyError {
+    pub fn at(&self) -> usize {
+        match *self {
+            VerifyError::InvalidOpcode { at, .. }
+            | VerifyError::MissingOperand { at, .. }
+            | VerifyError::ExtraOperand { at, .. }
+            | VerifyError::BadOperand { at, .. }
+            | VerifyError::BadRegister { at, .. }
+            | VerifyError::BadScale { at, .. }
+            | VerifyError::BadJump { at, .. }
+            | VerifyError::UndefinedFunction { at, .. } => at,
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: ", self.at())?;
+        match self {
+            VerifyError::InvalidOpcode { tp: TypeOp, value, .. } => write!(f, "invalid opcode {:#x}", value),
+            VerifyError::InvalidOpcode { tp, .. } => write!(f, "expected an op, found a {:?} operand", tp),
+            VerifyError::MissingOperand { op, index, .. } => write!(f, "{:?} is missing operand {}", op, index + 1),
+            VerifyError::ExtraOperand { op, .. } => write!(f, "too many operands for {:?}", op),
+            VerifyError::BadOperand { op, index, found, .. } => {
+                write!(f, "operand {} of {:?} cannot be {:?}", index + 1, op, found)
+            }
+            VerifyError::BadRegister { reg, .. } => {
+                write!(f, "register {} does not exist, there are {}", reg, REGISTER_COUNT)
+            }
+            VerifyError::BadScale { scale, .. } => write!(f, "index scale {} is not 1, 2, 4 or 8", scale),
+            VerifyError::BadJump { target, .. } => write!(f, "jump target {} is not the start of an instruction", target),
+            VerifyError::UndefinedFunction { name, .. } => write!(f, "function :{} is never declared", name),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+pub fn verify(stream: &ByteStream) -> Result<(), Vec<VerifyError>> {
+    let bytes = &stream.bytes;
+    let functions: Vec<u64> = symbols(stream)
+        .iter()
+        .map(|symbol| bytes[symbol.index + 1].unwrap())
+        .collect();
+    let mut errors = Vec::new();
+    //start of every instruction, and every (instruction, target) jump to check once they are known
+    let mut boundaries = vec![false; bytes.len() + 1];
+    boundaries[bytes.len()] = true;
+    let mut jumps = Vec::new();
+
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let at = pos;
+        let op = match decode(&bytes[at]) {
+            Some(op) => op,
+            None => {
+                errors.push(VerifyError::InvalidOpcode {
+                    at,
+                    tp: bytes[at].tp,
+                    value: bytes[at].unwrap(),
+                });
+                //carry on from the next op
+                pos += 1;
+                while pos < bytes.len() && !matches!(bytes[pos].tp, TypeOp) {
+                    pos += 1;
+                }
+                continue;
+            }
+        };
+        boundaries[at] = true;
+        pos += 1;
+
+        let operands_end = bytes[pos..]
+            .iter()
+            .position(|byte| matches!(byte.tp, TypeOp))
+            .map_or(bytes.len(), |n| pos + n);
+        let operands = &bytes[pos..operands_end];
+        let expected = match op {
+            STORE => match operands.get(1) {
+                Some(len) if len.tp.is_literal() => op.args().len() + len.unwrap() as usize,
+                _ => operands.len().max(op.args().len()),
+            },
+            //the argument count is optional
+            FUNC => operands.len().clamp(1, 2),
+            _ => op.args().len(),
+        };
+        if operands.len() < expected {
+            errors.push(VerifyError::MissingOperand {
+                at,
+                op,
+                index: operands.len(),
+            });
+        } else if operands.len() > expected {
+            errors.push(VerifyError::ExtraOperand { at, op });
+        }
+
+        for (index, operand) in operands.iter().enumerate().take(expected) {
+            let value = operand.unwrap();
+            if !op.accepts(index, operand.tp) {
+                errors.push(VerifyError::BadOperand {
+                    at,
+                    op,
+                    index,
+                    found: operand.tp,
+                });
+                continue;
+            }
+            match operand.tp {
+                TypeReg | DerefHeapReg | DerefStackReg if value >= REGISTER_COUNT as u64 => {
+                    errors.push(VerifyError::BadRegister { at, reg: value });
+                }
+                DerefIndexed => {
+                    let indexed = Indexed::unpack(operand.wide());
+                    for reg in [indexed.base, indexed.index] {
+                        if reg as usize >= REGISTER_COUNT {
+                            errors.push(VerifyError::BadRegister { at, reg: reg as u64 });
+                        }
+                    }
+                    if !indexed.valid_scale() {
+                        errors.push(VerifyError::BadScale { at, scale: indexed.scale });
+                    }
+                }
+                TypeJmp => jumps.push((at, value)),
+                TypeAddr | TypeU64 if matches!(op, JMP | JZ | JNZ | CALL) => jumps.push((at, value)),
+                //natives are registered with the engine, so their names cannot be checked here
+                TypeFunc if !(op == FUNC || op == NATIVE || functions.contains(&value)) => {
+                    errors.push(VerifyError::UndefinedFunction {
+                        at,
+                        name: func_name(value),
+                    });
+                }
+                _ => (),
+            }
+        }
+        pos = operands_end;
+    }
+
+    if !boundaries.get(stream.entry).copied().unwrap_or(false) {
+        jumps.push((stream.entry, stream.entry as u64));
+    }
+    for (at, target) in jumps {
+        let lands = usize::try_from(target)
+            .ok()
+            .and_then(|target| boundaries.get(target))
+            .copied()
+            .unwrap_or(false);
+        if !lands {
+            errors.push(VerifyError::BadJump { at, target });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        errors.sort_by_key(|err| err.at());
+        Err(errors)
+    }
+}
+
+fn decode(byte: &Byte) -> Option<Operations> {
+    match byte.tp {
+        TypeOp if byte.unwrap() <= u8::MAX as u64 => Operations::try_from(byte.unwrap() as u8).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::rvasm;
+    use crate::engine::{Engine, VmError};
+    use crate::{constant, emits, op, typed};
+
+    #[test]
+    fn well_formed_programs_pass() {
+        let program = rvasm(concat!(
+            ".func twice(a)\n    ADD %a %a\n    RET\n",
+            "MOV [1] 64u4\nCALL :twice\nJMP &end\nNOP\nend:\n",
+        ))
+        .unwrap();
+        assert_eq!(verify(&program), Ok(()));
+    }
+
+    #[test]
+    fn jumps_must_land_on_an_instruction() {
+        //j1 is the operand of the JMP itself
+        let program = emits![op!(JMP), typed!(TypeJmp, 1), op!(NOP)];
+        assert_eq!(verify(&program), Err(vec![VerifyError::BadJump { at: 0, target: 1 }]));
+        //one past the last instruction is the end of the program, which is fine
+        let program = emits![op!(JMP), typed!(TypeJmp, 3), op!(NOP)];
+        assert_eq!(verify(&program), Ok(()));
+        let program = emits![op!(JMP), typed!(TypeJmp, 4), op!(NOP)];
+        assert_eq!(verify(&program), Err(vec![VerifyError::BadJump { at: 0, target: 4 }]));
+    }
+
+    #[test]
+    fn registers_past_the_last_one_are_rejected() {
+        let last = REGISTER_COUNT as u64 - 1;
+        let program = emits![op!(MOV), typed!(TypeReg, last), constant!(1)];
+        assert_eq!(verify(&program), Ok(()));
+        let program = emits![op!(MOV), typed!(TypeReg, last + 1), constant!(1)];
+        assert_eq!(verify(&program), Err(vec![VerifyError::BadRegister { at: 0, reg: last + 1 }]));
+    }
+
+    #[test]
+    fn operand_counts_must_match_the_op() {
+        let program = emits![op!(MOV), typed!(TypeReg, 1), op!(NOP), constant!(1)];
+        assert_eq!(
+            verify(&program),
+            Err(vec![
+                VerifyError::MissingOperand { at: 0, op: MOV, index: 1 },
+                VerifyError::ExtraOperand { at: 2, op: NOP },
+            ])
+        );
+    }
+
+    #[test]
+    fn the_engine_refuses_unverified_programs() {
+        let program = emits![op!(MOV), typed!(TypeReg, 60), constant!(1)];
+        let mut engine = Engine::new();
+        engine.set_verify(true);
+        let status = engine.run(program);
+        let errors = vec![VerifyError::BadRegister { at: 0, reg: 60 }];
+        assert_eq!(status, Err(VmError::Unverified(errors)));
+        assert_eq!(engine.instructions(), 0);
+    }
+}